@@ -0,0 +1,100 @@
+use std::{env, fs, path::Path};
+
+use clap::Parser;
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use rcli::{process_csv, CsvOpts};
+
+/// rows of the generated fixture, `RCLI_BENCH_ROWS` overrides it
+const DEFAULT_ROWS: usize = 200_000;
+
+const POSITIONS: [&str; 5] = [
+    "Goalkeeper",
+    "Centre-Back",
+    "Left-Back",
+    "Central Midfield",
+    "Centre-Forward",
+];
+const NATIONS: [&str; 6] = [
+    "Italy",
+    "Poland",
+    "Brazil",
+    "Argentina",
+    "Portugal",
+    "Bosnia-Herzegovina",
+];
+
+/// A squad-like file with string, integer, float, boolean and date columns,
+/// the same every run
+fn write_fixture(path: &Path, rows: usize) -> anyhow::Result<()> {
+    let mut writer = csv::Writer::from_path(path)?;
+    writer.write_record([
+        "Id",
+        "Name",
+        "Position",
+        "DOB",
+        "Nationality",
+        "Kit Number",
+        "Height",
+        "Captain",
+    ])?;
+    for i in 0..rows {
+        writer.write_record([
+            i.to_string(),
+            format!("Player {}", i),
+            POSITIONS[i % POSITIONS.len()].to_string(),
+            format!("19{:02}-{:02}-{:02}", 70 + i % 30, 1 + i % 12, 1 + i % 28),
+            NATIONS[i % NATIONS.len()].to_string(),
+            (1 + i % 99).to_string(),
+            format!("1.{:02}", 60 + i % 40),
+            (i % 25 == 0).to_string(),
+        ])?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+fn bench_process_csv(c: &mut Criterion) {
+    let rows = env::var("RCLI_BENCH_ROWS")
+        .ok()
+        .and_then(|n| n.parse().ok())
+        .unwrap_or(DEFAULT_ROWS);
+    let dir = tempfile::tempdir().unwrap();
+    let input = dir.path().join("players.csv");
+    let output = dir.path().join("players.out");
+    write_fixture(&input, rows).unwrap();
+    let (input, output) = (input.to_str().unwrap(), output.to_str().unwrap());
+
+    let mut group = c.benchmark_group("process_csv");
+    group.sample_size(10);
+    group.throughput(Throughput::Bytes(fs::metadata(input).unwrap().len()));
+    // parsing alone, the serial part every thread count pays
+    group.bench_function("parse", |b| {
+        b.iter(|| {
+            let mut reader = csv::Reader::from_path(input).unwrap();
+            let mut record = csv::StringRecord::new();
+            while reader.read_record(&mut record).unwrap() {}
+        })
+    });
+    for format in ["json", "ndjson", "yaml"] {
+        // 0 is one thread per core
+        for threads in ["1", "0"] {
+            let opts = CsvOpts::parse_from([
+                "csv",
+                "-i",
+                input,
+                "-o",
+                output,
+                "--format",
+                format,
+                "--threads",
+                threads,
+            ]);
+            let id = BenchmarkId::new(format, format!("threads={}", threads));
+            group.bench_with_input(id, &opts, |b, opts| b.iter(|| process_csv(opts).unwrap()));
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_process_csv);
+criterion_main!(benches);
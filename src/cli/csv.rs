@@ -1,40 +1,494 @@
 use std::{
     fmt::{self, Display, Formatter},
+    path::PathBuf,
     str::FromStr,
 };
 
-use clap::Parser;
+use clap::{ArgAction, Parser};
+use enum_dispatch::enum_dispatch;
 
 use crate::CmdExcetor;
 
-use super::check_file_exist;
+use super::{check_file_exist, verify_path};
 
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum OutputFormat {
     Json,
     Yaml,
+    /// newline delimited json, one object per line
+    Ndjson,
+    /// array of `[[records]]` tables
+    Toml,
+    /// GitHub flavored markdown table
+    Markdown,
+    /// standalone html page with a table
+    Html,
+    Csv,
+    /// columnar Apache Parquet file
+    Parquet,
+    /// columnar Apache Arrow IPC file
+    Arrow,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Compression {
+    None,
+    Snappy,
+    Gzip,
+    Zstd,
+    Lz4,
+    Brotli,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DiffFormat {
+    Table,
+    Json,
+    /// `-`/`+` lines per row and field, like a unified diff
+    Patch,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum JoinType {
+    Inner,
+    Left,
+    Right,
+    /// full outer join
+    Full,
 }
 
 #[derive(Debug, Parser)]
+#[command(args_conflicts_with_subcommands = true)]
 pub struct CsvOpts {
-    /// Input file path
-    #[arg(short, long, value_parser=check_file_exist)]
+    #[command(subcommand)]
+    pub cmd: Option<CsvSubCommand>,
+
+    /// Input file path, `-` for stdin
+    #[arg(short, long, value_parser=check_file_exist, default_value = "-")]
+    pub input: String,
+
+    /// Output file path, stdout when omitted or `-`
+    #[arg(short, long)]
+    pub output: Option<String>,
+
+    #[command(flatten)]
+    pub reader: CsvReaderOpts,
+
+    /// Columns to keep, comma separated, in output order
+    #[arg(long, value_delimiter = ',')]
+    pub select: Vec<String>,
+
+    /// Rename output columns, old=new, comma separated or repeated
+    #[arg(long, value_delimiter = ',', value_parser = parse_rename)]
+    pub rename: Vec<(String, String)>,
+
+    /// Only keep rows matching an expression on the input columns, e.g.
+    /// "Nationality = 'Italy' and (`Kit Number` in 1..30 or Name ~ '^G')"
+    #[arg(long = "where")]
+    pub filter: Option<String>,
+
+    /// Emit every row as an array of values instead of an object
+    #[arg(long)]
+    pub arrays: bool,
+
+    /// Build nested objects and arrays from dotted headers such as
+    /// `address.city` or `tags[0]`. With --from, nested records are flattened
+    /// into such headers instead.
+    #[arg(long, conflicts_with = "arrays")]
+    pub nested: bool,
+
+    /// Output format: json, yaml, ndjson, toml, md, html, csv, parquet or arrow
+    #[arg(long,value_parser=parse_format, default_value = "json")]
+    pub format: OutputFormat,
+
+    /// Convert input of this format (json, yaml, ndjson or toml) back to CSV
+    #[arg(long, value_parser=parse_format)]
+    pub from: Option<OutputFormat>,
+
+    /// Keep every value as a string instead of inferring column types
+    #[arg(long)]
+    pub no_infer: bool,
+
+    /// Number of leading rows sampled to infer column types
+    #[arg(long, default_value_t = 1000)]
+    pub infer_rows: usize,
+
+    /// Column type schema (yaml or json map of column name to
+    /// boolean/integer/float/date/string), overrides inferred types
+    #[arg(long, value_parser=check_file_exist)]
+    pub schema: Option<String>,
+
+    /// Threads converting and serializing records, 0 for one per CPU core and
+    /// 1 for a single-threaded run. Output order is kept either way. Parsing
+    /// stays on one thread, so it bounds the speedup.
+    #[arg(long, default_value_t = 0)]
+    pub threads: usize,
+
+    /// Rows per row group of parquet output, or per record batch of arrow output
+    #[arg(long, default_value_t = 1024 * 1024, value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..))]
+    pub row_group_size: usize,
+
+    /// Compression of parquet or arrow output: none, snappy, gzip, zstd, lz4 or
+    /// brotli. Parquet uses snappy by default, arrow supports lz4 and zstd.
+    #[arg(long, value_parser=parse_compression)]
+    pub compression: Option<Compression>,
+}
+
+#[derive(Debug, Parser)]
+#[enum_dispatch(CmdExcetor)]
+pub enum CsvSubCommand {
+    #[command(about = "Profile the columns of a CSV file")]
+    Stats(CsvStatsOpts),
+    #[command(about = "Run a SQL query over a CSV file")]
+    Query(CsvQueryOpts),
+    #[command(about = "Validate a CSV file against a schema, exits non-zero on violations")]
+    Validate(CsvValidateOpts),
+    #[command(about = "Show added, removed and changed rows between two CSV files")]
+    Diff(CsvDiffOpts),
+    #[command(about = "Sort rows by one or more columns, on disk for large files")]
+    Sort(CsvSortOpts),
+    #[command(about = "Drop repeated rows, keeping the first one")]
+    Dedup(CsvDedupOpts),
+    #[command(about = "Split a CSV file by row count or by the value of a column")]
+    Split(CsvSplitOpts),
+    #[command(
+        about = "Concatenate CSV files under the union of their headers",
+        alias = "merge"
+    )]
+    Cat(CsvCatOpts),
+    #[command(about = "Join two CSV files on key columns")]
+    Join(CsvJoinOpts),
+    #[command(about = "Preview rows of a CSV file as an aligned table")]
+    View(CsvViewOpts),
+}
+
+#[derive(Debug, Parser)]
+pub struct CsvStatsOpts {
+    /// Input file path, `-` for stdin
+    #[arg(short, long, value_parser=check_file_exist, default_value = "-")]
+    pub input: String,
+
+    /// Output file path, stdout when omitted or `-`
+    #[arg(short, long)]
+    pub output: Option<String>,
+
+    #[command(flatten)]
+    pub reader: CsvReaderOpts,
+
+    /// Number of most frequent values listed per column
+    #[arg(long, default_value_t = 5)]
+    pub top: usize,
+
+    /// Estimate distinct counts and top values in bounded memory (HyperLogLog)
+    #[arg(long)]
+    pub approx: bool,
+
+    /// Output format, a terminal table when omitted
+    #[arg(long, value_parser=parse_format)]
+    pub format: Option<OutputFormat>,
+}
+
+#[derive(Debug, Parser)]
+pub struct CsvQueryOpts {
+    /// Query such as "SELECT Nationality, count(*) FROM t GROUP BY Nationality",
+    /// the table name is free
+    pub query: String,
+
+    /// Input file path, `-` for stdin
+    #[arg(short, long, value_parser=check_file_exist, default_value = "-")]
+    pub input: String,
+
+    /// Output file path, stdout when omitted or `-`
+    #[arg(short, long)]
+    pub output: Option<String>,
+
+    #[command(flatten)]
+    pub reader: CsvReaderOpts,
+
+    /// Output format: json, yaml, ndjson, toml, md, html or csv
+    #[arg(long, value_parser=parse_format, default_value = "json")]
+    pub format: OutputFormat,
+}
+
+#[derive(Debug, Parser)]
+pub struct CsvValidateOpts {
+    /// Input file path, `-` for stdin
+    #[arg(value_parser=check_file_exist, default_value = "-")]
+    pub input: String,
+
+    /// Schema file (yaml or json) with per column type, required, nullable,
+    /// pattern, enum, min, max and unique rules
+    #[arg(long, value_parser=check_file_exist)]
+    pub schema: String,
+
+    /// Report file path, stdout when omitted or `-`
+    #[arg(short, long)]
+    pub output: Option<String>,
+
+    #[command(flatten)]
+    pub reader: CsvReaderOpts,
+
+    /// Report format, a terminal table when omitted
+    #[arg(long, value_parser=parse_format)]
+    pub format: Option<OutputFormat>,
+}
+
+#[derive(Debug, Parser)]
+pub struct CsvDiffOpts {
+    /// Old file path
+    #[arg(value_parser=check_file_exist)]
+    pub old: String,
+
+    /// New file path
+    #[arg(value_parser=check_file_exist)]
+    pub new: String,
+
+    /// Primary key columns matching rows across the files, comma separated
+    #[arg(long, value_delimiter = ',', required = true)]
+    pub key: Vec<String>,
+
+    /// Output file path, stdout when omitted or `-`
+    #[arg(short, long)]
+    pub output: Option<String>,
+
+    #[command(flatten)]
+    pub reader: CsvReaderOpts,
+
+    /// Output format: table, json or patch
+    #[arg(long, value_parser=parse_diff_format, default_value = "table")]
+    pub format: DiffFormat,
+
+    /// Memory for sorting each file in MiB, larger files are sorted on disk
+    #[arg(long, default_value_t = 256)]
+    pub memory_limit: usize,
+}
+
+#[derive(Debug, Parser)]
+pub struct CsvSortOpts {
+    /// Input file path, `-` for stdin
+    #[arg(short, long, value_parser=check_file_exist, default_value = "-")]
+    pub input: String,
+
+    /// Output file path, stdout when omitted or `-`
+    #[arg(short, long)]
+    pub output: Option<String>,
+
+    #[command(flatten)]
+    pub reader: CsvReaderOpts,
+
+    /// Sort keys, comma separated, most significant first. A key is a column
+    /// name with optional flags: `:n` numeric, `:r` descending, e.g. "Nationality,Kit Number:nr"
+    #[arg(short, long, value_delimiter = ',', value_parser = parse_sort_key, required = true)]
+    pub key: Vec<SortKey>,
+
+    /// Memory for sorting in MiB, larger inputs are sorted on disk
+    #[arg(long, default_value_t = 256)]
+    pub memory_limit: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SortKey {
+    pub column: String,
+    /// numbers first in numeric order, then the other values as text
+    pub numeric: bool,
+    pub reverse: bool,
+}
+
+#[derive(Debug, Parser)]
+pub struct CsvDedupOpts {
+    /// Input file path, `-` for stdin
+    #[arg(short, long, value_parser=check_file_exist, default_value = "-")]
+    pub input: String,
+
+    /// Output file path, stdout when omitted or `-`
+    #[arg(short, long)]
+    pub output: Option<String>,
+
+    #[command(flatten)]
+    pub reader: CsvReaderOpts,
+
+    /// Columns deciding whether two rows are the same, comma separated, the
+    /// whole row when omitted
+    #[arg(short, long, value_delimiter = ',')]
+    pub key: Vec<String>,
+}
+
+#[derive(Debug, Parser)]
+pub struct CsvSplitOpts {
+    /// Input file path, `-` for stdin
+    #[arg(short, long, value_parser=check_file_exist, default_value = "-")]
     pub input: String,
 
-    /// Output file path
+    #[command(flatten)]
+    pub reader: CsvReaderOpts,
+
+    /// Rows per output file
+    #[arg(long, conflicts_with = "by", required_unless_present = "by", value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..))]
+    pub rows: Option<usize>,
+
+    /// Write one file per distinct value of this column. At most 64 files are
+    /// open at a time, others are closed and appended to later.
+    #[arg(long)]
+    pub by: Option<String>,
+
+    /// Directory the parts are written to, named <prefix>_<n>.csv or <prefix>_<value>.csv
+    #[arg(long, value_parser=verify_path, default_value = ".")]
+    pub output_dir: PathBuf,
+
+    /// File name prefix of the parts
+    #[arg(long, default_value = "part")]
+    pub prefix: String,
+}
+
+#[derive(Debug, Parser)]
+pub struct CsvCatOpts {
+    /// Input file paths
+    #[arg(required = true, value_parser=check_file_exist)]
+    pub inputs: Vec<String>,
+
+    /// Output file path, stdout when omitted or `-`
     #[arg(short, long)]
     pub output: Option<String>,
 
+    #[command(flatten)]
+    pub reader: CsvReaderOpts,
+}
+
+#[derive(Debug, Parser)]
+pub struct CsvJoinOpts {
+    /// Left file path
+    #[arg(value_parser=check_file_exist)]
+    pub left: String,
+
+    /// Right file path
+    #[arg(value_parser=check_file_exist)]
+    pub right: String,
+
+    /// Key columns, comma separated, each a name shared by both files or left=right
+    #[arg(long, value_delimiter = ',', required = true)]
+    pub on: Vec<String>,
+
+    /// Join type: inner, left, right or full
+    #[arg(long = "type", value_parser=parse_join_type, default_value = "inner")]
+    pub join_type: JoinType,
+
+    /// Output file path, stdout when omitted or `-`
+    #[arg(short, long)]
+    pub output: Option<String>,
+
+    #[command(flatten)]
+    pub reader: CsvReaderOpts,
+
+    /// Output format: csv, json, yaml, ndjson, toml, md or html
+    #[arg(long, value_parser=parse_format, default_value = "csv")]
+    pub format: OutputFormat,
+
+    /// Memory in MiB for the in-memory (hash join) side, when both files are
+    /// larger they are sorted on disk and merged
+    #[arg(long, default_value_t = 256)]
+    pub memory_limit: usize,
+}
+
+#[derive(Debug, Parser)]
+pub struct CsvViewOpts {
+    /// Input file path, `-` for stdin
+    #[arg(value_parser=check_file_exist, default_value = "-")]
+    pub input: String,
+
+    #[command(flatten)]
+    pub reader: CsvReaderOpts,
+
+    /// Number of rows shown, the page size with --page
+    #[arg(short = 'n', long, default_value_t = 20, value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..))]
+    pub rows: usize,
+
+    /// Page to show (1-based), `--page 3` with 20 rows shows rows 41 to 60
+    #[arg(long, default_value_t = 1, value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..))]
+    pub page: usize,
+
+    /// Widest a column gets in terminal cells, longer values are cut with `…`
+    #[arg(long, default_value_t = 30)]
+    pub max_width: usize,
+
+    /// Number of leading rows column widths are computed from, so every page
+    /// lines up the same way
+    #[arg(long, default_value_t = 100)]
+    pub sample: usize,
+}
+
+/// How the CSV input is parsed, shared by every command reading CSV
+#[derive(Debug, Clone, Parser)]
+pub struct CsvReaderOpts {
     /// CSV delimiter
     #[arg(short, long, default_value_t = ',')]
     pub delimiter: char,
 
-    /// CSV has header or not
-    #[arg(long, default_value_t = true)]
+    /// Quote character
+    #[arg(long, default_value_t = '"')]
+    pub quote: char,
+
+    /// Escape character for quotes inside quoted fields, by default quotes are
+    /// escaped by doubling them
+    #[arg(long)]
+    pub escape: Option<char>,
+
+    /// Lines starting with this character are skipped
+    #[arg(long)]
+    pub comment: Option<char>,
+
+    /// Allow rows with a different number of fields
+    #[arg(long)]
+    pub flexible: bool,
+
+    /// Guess delimiter and quote from the first few KB of the input
+    #[arg(long)]
+    pub auto: bool,
+
+    /// Input encoding such as utf-8, windows-1252, latin1 or utf-16le. `auto`
+    /// follows a byte order mark or guesses from the head of the input.
+    #[arg(long, default_value = "auto")]
+    pub encoding: String,
+
+    /// CSV has header or not, `--header false` reads the first row as data
+    #[arg(long, default_value_t = true, num_args = 0..=1, default_missing_value = "true", action = ArgAction::Set)]
     pub header: bool,
 
-    #[arg(long,value_parser=parse_format, default_value = "json")]
-    pub format: OutputFormat,
+    /// Column names, comma separated, used instead of the header row or the
+    /// generated col0..colN names of a headerless file
+    #[arg(long, value_delimiter = ',')]
+    pub columns: Vec<String>,
+
+    /// Worksheet of an xlsx/xls/ods input, by name or 1-based position, the
+    /// first sheet by default
+    #[arg(long)]
+    pub sheet: Option<String>,
+
+    /// Sheet row (1-based) holding the header of an xlsx/xls/ods input, rows
+    /// above it are skipped
+    #[arg(long, value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..))]
+    pub header_row: Option<usize>,
+}
+
+fn parse_rename(s: &str) -> Result<(String, String), anyhow::Error> {
+    match s.split_once('=') {
+        Some((old, new)) if !old.is_empty() && !new.is_empty() => Ok((old.into(), new.into())),
+        _ => Err(anyhow::anyhow!("Rename should be old=new")),
+    }
+}
+
+fn parse_sort_key(s: &str) -> Result<SortKey, anyhow::Error> {
+    let (column, flags) = match s.rsplit_once(':') {
+        Some((column, flags)) if flags.chars().all(|c| c == 'n' || c == 'r') => (column, flags),
+        _ => (s, ""),
+    };
+    if column.is_empty() {
+        return Err(anyhow::anyhow!("Sort key should be column[:n][:r]"));
+    }
+    Ok(SortKey {
+        column: column.into(),
+        numeric: flags.contains('n'),
+        reverse: flags.contains('r'),
+    })
 }
 
 fn parse_format(s: &str) -> Result<OutputFormat, anyhow::Error> {
@@ -46,6 +500,13 @@ impl From<OutputFormat> for String {
         match f {
             OutputFormat::Json => "json".into(),
             OutputFormat::Yaml => "yaml".into(),
+            OutputFormat::Ndjson => "ndjson".into(),
+            OutputFormat::Toml => "toml".into(),
+            OutputFormat::Markdown => "md".into(),
+            OutputFormat::Html => "html".into(),
+            OutputFormat::Csv => "csv".into(),
+            OutputFormat::Parquet => "parquet".into(),
+            OutputFormat::Arrow => "arrow".into(),
         }
     }
 }
@@ -57,6 +518,13 @@ impl FromStr for OutputFormat {
         match s {
             "json" => Ok(OutputFormat::Json),
             "yaml" => Ok(OutputFormat::Yaml),
+            "ndjson" => Ok(OutputFormat::Ndjson),
+            "toml" => Ok(OutputFormat::Toml),
+            "md" | "markdown" => Ok(OutputFormat::Markdown),
+            "html" => Ok(OutputFormat::Html),
+            "csv" => Ok(OutputFormat::Csv),
+            "parquet" => Ok(OutputFormat::Parquet),
+            "arrow" => Ok(OutputFormat::Arrow),
             _ => Err(anyhow::anyhow!("Invalid format")),
         }
     }
@@ -68,13 +536,182 @@ impl Display for OutputFormat {
     }
 }
 
+fn parse_compression(s: &str) -> Result<Compression, anyhow::Error> {
+    s.parse::<Compression>()
+}
+
+impl From<Compression> for String {
+    fn from(c: Compression) -> Self {
+        match c {
+            Compression::None => "none".into(),
+            Compression::Snappy => "snappy".into(),
+            Compression::Gzip => "gzip".into(),
+            Compression::Zstd => "zstd".into(),
+            Compression::Lz4 => "lz4".into(),
+            Compression::Brotli => "brotli".into(),
+        }
+    }
+}
+
+impl FromStr for Compression {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" | "uncompressed" => Ok(Compression::None),
+            "snappy" => Ok(Compression::Snappy),
+            "gzip" => Ok(Compression::Gzip),
+            "zstd" => Ok(Compression::Zstd),
+            "lz4" => Ok(Compression::Lz4),
+            "brotli" => Ok(Compression::Brotli),
+            _ => Err(anyhow::anyhow!("Invalid compression")),
+        }
+    }
+}
+
+impl Display for Compression {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", String::from(*self))
+    }
+}
+
+fn parse_diff_format(s: &str) -> Result<DiffFormat, anyhow::Error> {
+    s.parse::<DiffFormat>()
+}
+
+impl From<DiffFormat> for String {
+    fn from(f: DiffFormat) -> Self {
+        match f {
+            DiffFormat::Table => "table".into(),
+            DiffFormat::Json => "json".into(),
+            DiffFormat::Patch => "patch".into(),
+        }
+    }
+}
+
+impl FromStr for DiffFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "table" => Ok(DiffFormat::Table),
+            "json" => Ok(DiffFormat::Json),
+            "patch" => Ok(DiffFormat::Patch),
+            _ => Err(anyhow::anyhow!("Invalid format")),
+        }
+    }
+}
+
+impl Display for DiffFormat {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", String::from(*self))
+    }
+}
+
+fn parse_join_type(s: &str) -> Result<JoinType, anyhow::Error> {
+    s.parse::<JoinType>()
+}
+
+impl From<JoinType> for String {
+    fn from(t: JoinType) -> Self {
+        match t {
+            JoinType::Inner => "inner".into(),
+            JoinType::Left => "left".into(),
+            JoinType::Right => "right".into(),
+            JoinType::Full => "full".into(),
+        }
+    }
+}
+
+impl FromStr for JoinType {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "inner" => Ok(JoinType::Inner),
+            "left" => Ok(JoinType::Left),
+            "right" => Ok(JoinType::Right),
+            "full" | "outer" => Ok(JoinType::Full),
+            _ => Err(anyhow::anyhow!("Invalid join type")),
+        }
+    }
+}
+
+impl Display for JoinType {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", String::from(*self))
+    }
+}
+
 impl CmdExcetor for CsvOpts {
     async fn execute(self) -> anyhow::Result<()> {
-        let output = if let Some(output) = self.output {
-            output.clone()
+        let result = if let Some(cmd) = self.cmd {
+            cmd.execute().await
+        } else if self.from.is_some() {
+            crate::process_to_csv(&self)
         } else {
-            format!("output.{}", self.format)
+            crate::process_csv(&self)
         };
-        crate::process_csv(&self.input, &output, &self.format)
+        crate::ignore_broken_pipe(result)
+    }
+}
+
+impl CmdExcetor for CsvStatsOpts {
+    async fn execute(self) -> anyhow::Result<()> {
+        crate::process_csv_stats(&self)
+    }
+}
+
+impl CmdExcetor for CsvValidateOpts {
+    async fn execute(self) -> anyhow::Result<()> {
+        crate::process_csv_validate(&self)
+    }
+}
+
+impl CmdExcetor for CsvQueryOpts {
+    async fn execute(self) -> anyhow::Result<()> {
+        crate::process_csv_query(&self)
+    }
+}
+
+impl CmdExcetor for CsvDiffOpts {
+    async fn execute(self) -> anyhow::Result<()> {
+        crate::process_csv_diff(&self)
+    }
+}
+
+impl CmdExcetor for CsvSortOpts {
+    async fn execute(self) -> anyhow::Result<()> {
+        crate::process_csv_sort(&self)
+    }
+}
+
+impl CmdExcetor for CsvDedupOpts {
+    async fn execute(self) -> anyhow::Result<()> {
+        crate::process_csv_dedup(&self)
+    }
+}
+
+impl CmdExcetor for CsvSplitOpts {
+    async fn execute(self) -> anyhow::Result<()> {
+        crate::process_csv_split(&self)
+    }
+}
+
+impl CmdExcetor for CsvCatOpts {
+    async fn execute(self) -> anyhow::Result<()> {
+        crate::process_csv_cat(&self)
+    }
+}
+
+impl CmdExcetor for CsvJoinOpts {
+    async fn execute(self) -> anyhow::Result<()> {
+        crate::process_csv_join(&self)
+    }
+}
+
+impl CmdExcetor for CsvViewOpts {
+    async fn execute(self) -> anyhow::Result<()> {
+        crate::process_csv_view(&self)
     }
 }
@@ -1,34 +1,219 @@
-use clap::Parser;
+use std::{
+    fmt::{self, Display, Formatter},
+    str::FromStr,
+};
+
+use clap::{ArgAction, Parser};
 
 use crate::CmdExcetor;
-use zxcvbn::zxcvbn;
+
+use super::check_file_exist;
 
 #[derive(Debug, Parser)]
 pub struct GenPassOpts {
-    /// Length of the password
-    #[arg(short, long, default_value_t = 16)]
-    pub length: u8,
+    /// Length of the password, 16 or 6 for a PIN
+    #[arg(short, long)]
+    pub length: Option<u8>,
 
-    #[arg(long, default_value_t = true)]
+    /// Include uppercase letters, `--uppercase false` leaves them out
+    #[arg(long, default_value_t = true, num_args = 0..=1, default_missing_value = "true", action = ArgAction::Set)]
     pub uppercase: bool,
 
-    #[arg(long, default_value_t = true)]
+    /// Include lowercase letters, `--lowercase false` leaves them out
+    #[arg(long, default_value_t = true, num_args = 0..=1, default_missing_value = "true", action = ArgAction::Set)]
     pub lowercase: bool,
 
-    #[arg(long, default_value_t = true)]
+    /// Include digits, `--number false` leaves them out
+    #[arg(long, default_value_t = true, num_args = 0..=1, default_missing_value = "true", action = ArgAction::Set)]
     pub number: bool,
 
-    /// Number of passwords to generate
-    #[arg(long, default_value_t = true)]
+    /// Include symbols, `--symbol false` leaves them out
+    #[arg(long, default_value_t = true, num_args = 0..=1, default_missing_value = "true", action = ArgAction::Set)]
     pub symbol: bool,
+
+    /// Generate a numeric PIN, only digits whatever the class options say
+    #[arg(long, conflicts_with_all = ["words", "charset", "symbols"])]
+    pub pin: bool,
+
+    /// Characters to draw the password from instead of the built-in classes,
+    /// any Unicode characters. They still count as uppercase, lowercase,
+    /// digits or symbols for the class options and policies.
+    #[arg(long, conflicts_with = "words", allow_hyphen_values = true)]
+    pub charset: Option<String>,
+
+    /// Symbols used instead of the default !@#$%^&*_
+    #[arg(long, conflicts_with_all = ["charset", "words"], allow_hyphen_values = true)]
+    pub symbols: Option<String>,
+
+    /// Characters never used in the password
+    #[arg(long, conflicts_with = "words", allow_hyphen_values = true)]
+    pub exclude: Option<String>,
+
+    /// Leave out characters easily mistaken for one another, such as 0, O and
+    /// o or 1, l, I and |
+    #[arg(long, conflicts_with = "words")]
+    pub exclude_ambiguous: bool,
+
+    /// Policy the password has to follow, a TOML file or one of the presets
+    /// aws-iam, active-directory and mysql
+    #[arg(long, conflicts_with = "words")]
+    pub policy: Option<String>,
+
+    /// Number of passwords to generate
+    #[arg(short, long, default_value_t = 1, value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..))]
+    pub count: usize,
+
+    /// Output format: plain (one password per line), json or csv. json and csv
+    /// include the zxcvbn score, crack time and entropy of every password.
+    #[arg(long, value_parser = parse_pass_output, default_value = "plain")]
+    pub output: PassOutput,
+
+    /// Generate a passphrase of this many words instead of a password
+    #[arg(long, conflicts_with = "length", value_parser = clap::builder::RangedU64ValueParser::<u8>::new().range(1..=255))]
+    pub words: Option<u8>,
+
+    /// Word list file used instead of the embedded list, one word per line.
+    /// Diceware lists with a dice roll before each word work as well.
+    #[arg(long, requires = "words", value_parser = check_file_exist)]
+    pub wordlist: Option<String>,
+
+    /// Separator between the words of a passphrase
+    #[arg(long, default_value = "-", requires = "words")]
+    pub separator: String,
+
+    /// Capitalization of passphrase words: lower, upper, title or random
+    /// (each word lower or title case)
+    #[arg(long = "case", value_parser = parse_word_case, default_value = "lower", requires = "words")]
+    pub word_case: WordCase,
+
+    /// Number of random digits added to the words of a passphrase
+    #[arg(long, default_value_t = 0, requires = "words")]
+    pub digits: u8,
+}
+
+/// The same options as `rcli genpass` without arguments
+impl Default for GenPassOpts {
+    fn default() -> Self {
+        Self {
+            length: None,
+            uppercase: true,
+            lowercase: true,
+            number: true,
+            symbol: true,
+            pin: false,
+            charset: None,
+            symbols: None,
+            exclude: None,
+            exclude_ambiguous: false,
+            policy: None,
+            count: 1,
+            output: PassOutput::Plain,
+            words: None,
+            wordlist: None,
+            separator: "-".to_string(),
+            word_case: WordCase::Lower,
+            digits: 0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PassOutput {
+    Plain,
+    Json,
+    Csv,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WordCase {
+    Lower,
+    Upper,
+    /// first letter upper case
+    Title,
+    /// every word lower or title case at random, one extra bit per word
+    Random,
 }
 
 impl CmdExcetor for GenPassOpts {
     async fn execute(self) -> anyhow::Result<()> {
-        let password = crate::process_genpass(self)?;
-        println!("{}", password);
-        let estimate = zxcvbn(&password, &[]);
-        eprintln!("{:?}", estimate.score());
+        let reports = crate::process_genpass_batch(&self)?;
+        print!("{}", crate::format_reports(&reports, self.output)?);
+        if let [report] = reports.as_slice() {
+            if self.output == PassOutput::Plain {
+                eprintln!(
+                    "score: {}/4, crack time: {}, entropy: {:.1} bits",
+                    report.score, report.crack_time, report.entropy
+                );
+            }
+        }
         Ok(())
     }
 }
+
+fn parse_pass_output(s: &str) -> Result<PassOutput, anyhow::Error> {
+    s.parse::<PassOutput>()
+}
+
+impl From<PassOutput> for String {
+    fn from(o: PassOutput) -> Self {
+        match o {
+            PassOutput::Plain => "plain".into(),
+            PassOutput::Json => "json".into(),
+            PassOutput::Csv => "csv".into(),
+        }
+    }
+}
+
+impl FromStr for PassOutput {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "plain" => Ok(PassOutput::Plain),
+            "json" => Ok(PassOutput::Json),
+            "csv" => Ok(PassOutput::Csv),
+            _ => Err(anyhow::anyhow!("Invalid output format")),
+        }
+    }
+}
+
+impl Display for PassOutput {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", String::from(*self))
+    }
+}
+
+fn parse_word_case(s: &str) -> Result<WordCase, anyhow::Error> {
+    s.parse::<WordCase>()
+}
+
+impl From<WordCase> for String {
+    fn from(c: WordCase) -> Self {
+        match c {
+            WordCase::Lower => "lower".into(),
+            WordCase::Upper => "upper".into(),
+            WordCase::Title => "title".into(),
+            WordCase::Random => "random".into(),
+        }
+    }
+}
+
+impl FromStr for WordCase {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "lower" => Ok(WordCase::Lower),
+            "upper" => Ok(WordCase::Upper),
+            "title" => Ok(WordCase::Title),
+            "random" => Ok(WordCase::Random),
+            _ => Err(anyhow::anyhow!("Invalid word case")),
+        }
+    }
+}
+
+impl Display for WordCase {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", String::from(*self))
+    }
+}
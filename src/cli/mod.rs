@@ -26,10 +26,12 @@ pub struct Opts {
     pub cmd: Subcommand,
 }
 
+// parsed once per run, so the size of the csv options does not matter
+#[allow(clippy::large_enum_variant)]
 #[derive(Debug, Parser)]
 #[enum_dispatch(CmdExcetor)]
 pub enum Subcommand {
-    #[command(name = "csv", about = "Convert CSV to JSON")]
+    #[command(name = "csv", about = "Convert, profile and query CSV files")]
     Csv(CsvOpts),
     #[command(name = "genpass", about = "Generate random password")]
     GenPass(GenPassOpts),
@@ -0,0 +1,336 @@
+use std::{io::Write, sync::Arc};
+
+use arrow_array::{
+    builder::{BooleanBuilder, Float64Builder, Int64Builder, StringBuilder},
+    ArrayRef, RecordBatch,
+};
+use arrow_ipc::{
+    writer::{FileWriter, IpcWriteOptions},
+    CompressionType,
+};
+use arrow_schema::{DataType, Field, Schema, SchemaRef};
+use parquet::{
+    arrow::ArrowWriter,
+    basic::{self, BrotliLevel, GzipLevel, ZstdLevel},
+    file::properties::WriterProperties,
+};
+use serde_json::Value;
+
+use crate::{
+    cli::{Compression, CsvOpts, OutputFormat},
+    writer_for_output,
+};
+
+use super::{csv_infer::ColumnType, csv_writer::value_to_cell};
+
+/// parquet splits row groups itself, it is fed batches of at most this many rows
+const MAX_BATCH_ROWS: usize = 64 * 1024;
+
+type Output = Box<dyn Write + Send>;
+
+enum Sink {
+    Parquet(ArrowWriter<Output>),
+    Arrow(FileWriter<Output>),
+}
+
+/// One typed column being filled for the next batch
+enum ColumnBuilder {
+    Boolean(BooleanBuilder),
+    Integer(Int64Builder),
+    Float(Float64Builder),
+    String(StringBuilder),
+}
+
+/// Collects converted records into typed columns and writes them as parquet
+/// row groups or arrow record batches. Dates stay ISO 8601 strings, as in the
+/// other output formats.
+pub struct ColumnarWriter {
+    schema: SchemaRef,
+    types: Vec<ColumnType>,
+    columns: Vec<ColumnBuilder>,
+    batch_rows: usize,
+    rows: usize,
+    sink: Sink,
+}
+
+impl ColumnarWriter {
+    pub fn new(opts: &CsvOpts, names: &[String], types: &[ColumnType]) -> anyhow::Result<Self> {
+        // records are objects, two columns of one name would share a cell
+        if let Some(name) = names
+            .iter()
+            .enumerate()
+            .find_map(|(i, name)| names[..i].contains(name).then_some(name))
+        {
+            anyhow::bail!(
+                "Column {:?} appears twice, {} output needs distinct column names, rename one with --rename",
+                name,
+                opts.format
+            );
+        }
+        let schema = Arc::new(Schema::new(
+            names
+                .iter()
+                .zip(types)
+                .map(|(name, t)| Field::new(name, data_type(*t), true))
+                .collect::<Vec<_>>(),
+        ));
+        let output = writer_for_output(opts.output.as_deref())?;
+        let (sink, batch_rows) = match opts.format {
+            OutputFormat::Parquet => {
+                let props = WriterProperties::builder()
+                    .set_max_row_group_size(opts.row_group_size)
+                    .set_compression(parquet_compression(
+                        opts.compression.unwrap_or(Compression::Snappy),
+                    ))
+                    .build();
+                let writer = ArrowWriter::try_new(output, schema.clone(), Some(props))?;
+                (
+                    Sink::Parquet(writer),
+                    opts.row_group_size.min(MAX_BATCH_ROWS),
+                )
+            }
+            OutputFormat::Arrow => {
+                let compression = match opts.compression {
+                    None | Some(Compression::None) => None,
+                    Some(Compression::Lz4) => Some(CompressionType::LZ4_FRAME),
+                    Some(Compression::Zstd) => Some(CompressionType::ZSTD),
+                    Some(c) => {
+                        anyhow::bail!("arrow output supports lz4 or zstd compression, not {}", c)
+                    }
+                };
+                let options = IpcWriteOptions::default().try_with_compression(compression)?;
+                let writer = FileWriter::try_new_with_options(output, &schema, options)?;
+                (Sink::Arrow(writer), opts.row_group_size)
+            }
+            format => anyhow::bail!("{} is not a columnar format", format),
+        };
+        Ok(Self {
+            schema,
+            types: types.to_vec(),
+            columns: types.iter().map(|t| ColumnBuilder::new(*t)).collect(),
+            batch_rows,
+            rows: 0,
+            sink,
+        })
+    }
+
+    /// `record` is an object or an array of cells, in column order
+    pub fn write(&mut self, record: &Value) -> anyhow::Result<()> {
+        let cells: Box<dyn Iterator<Item = &Value>> = match record {
+            Value::Object(map) => Box::new(map.values()),
+            Value::Array(values) => Box::new(values.iter()),
+            _ => anyhow::bail!("Columnar output requires records with columns"),
+        };
+        for (i, (column, cell)) in self.columns.iter_mut().zip(cells).enumerate() {
+            if !column.append(cell) {
+                anyhow::bail!(
+                    "Value {} of column {:?} is not {}, name the column type with --schema",
+                    cell,
+                    self.schema.field(i).name(),
+                    self.types[i]
+                );
+            }
+        }
+        self.rows += 1;
+        if self.rows == self.batch_rows {
+            self.flush_batch()?;
+        }
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> anyhow::Result<()> {
+        if self.rows > 0 {
+            self.flush_batch()?;
+        }
+        let mut output = match self.sink {
+            Sink::Parquet(writer) => writer.into_inner()?,
+            Sink::Arrow(writer) => writer.into_inner()?,
+        };
+        output.flush()?;
+        Ok(())
+    }
+
+    fn flush_batch(&mut self) -> anyhow::Result<()> {
+        let arrays = self.columns.iter_mut().map(ColumnBuilder::finish).collect();
+        let batch = RecordBatch::try_new(self.schema.clone(), arrays)?;
+        match &mut self.sink {
+            Sink::Parquet(writer) => writer.write(&batch)?,
+            Sink::Arrow(writer) => writer.write(&batch)?,
+        }
+        self.rows = 0;
+        Ok(())
+    }
+}
+
+impl ColumnBuilder {
+    fn new(t: ColumnType) -> Self {
+        match t {
+            ColumnType::Boolean => ColumnBuilder::Boolean(BooleanBuilder::new()),
+            ColumnType::Integer => ColumnBuilder::Integer(Int64Builder::new()),
+            ColumnType::Float => ColumnBuilder::Float(Float64Builder::new()),
+            ColumnType::Date | ColumnType::String => ColumnBuilder::String(StringBuilder::new()),
+        }
+    }
+
+    /// false when the cell does not fit the column, such as text in an integer
+    /// column beyond the rows types were inferred from
+    fn append(&mut self, cell: &Value) -> bool {
+        match (self, cell) {
+            (ColumnBuilder::Boolean(b), Value::Null) => b.append_null(),
+            (ColumnBuilder::Integer(b), Value::Null) => b.append_null(),
+            (ColumnBuilder::Float(b), Value::Null) => b.append_null(),
+            (ColumnBuilder::String(b), Value::Null) => b.append_null(),
+            (ColumnBuilder::Boolean(b), Value::Bool(v)) => b.append_value(*v),
+            (ColumnBuilder::Integer(b), Value::Number(n)) if n.is_i64() => {
+                b.append_option(n.as_i64())
+            }
+            (ColumnBuilder::Float(b), Value::Number(n)) => b.append_option(n.as_f64()),
+            (ColumnBuilder::String(b), cell) => b.append_value(value_to_cell(cell)),
+            _ => return false,
+        }
+        true
+    }
+
+    fn finish(&mut self) -> ArrayRef {
+        match self {
+            ColumnBuilder::Boolean(b) => Arc::new(b.finish()),
+            ColumnBuilder::Integer(b) => Arc::new(b.finish()),
+            ColumnBuilder::Float(b) => Arc::new(b.finish()),
+            ColumnBuilder::String(b) => Arc::new(b.finish()),
+        }
+    }
+}
+
+fn data_type(t: ColumnType) -> DataType {
+    match t {
+        ColumnType::Boolean => DataType::Boolean,
+        ColumnType::Integer => DataType::Int64,
+        ColumnType::Float => DataType::Float64,
+        ColumnType::Date | ColumnType::String => DataType::Utf8,
+    }
+}
+
+fn parquet_compression(c: Compression) -> basic::Compression {
+    match c {
+        Compression::None => basic::Compression::UNCOMPRESSED,
+        Compression::Snappy => basic::Compression::SNAPPY,
+        Compression::Gzip => basic::Compression::GZIP(GzipLevel::default()),
+        Compression::Zstd => basic::Compression::ZSTD(ZstdLevel::default()),
+        Compression::Lz4 => basic::Compression::LZ4_RAW,
+        Compression::Brotli => basic::Compression::BROTLI(BrotliLevel::default()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+
+    use arrow_array::{cast::AsArray, types::Int64Type};
+    use arrow_ipc::reader::FileReader;
+    use clap::Parser;
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+
+    use crate::process_csv;
+
+    use super::*;
+
+    const SQUAD: &str = "Name,Kit Number,Height,Captain,DOB\n\
+        Buffon,77,1.92,false,1978-01-28\n\
+        Szczesny,1,1.95,false,1990-04-18\n\
+        Chiellini,3,,true,1984-08-14\n";
+
+    fn convert(args: &[&str]) -> anyhow::Result<(tempfile::TempDir, std::path::PathBuf)> {
+        let dir = tempfile::tempdir()?;
+        let input = dir.path().join("squad.csv");
+        let output = dir.path().join("squad.out");
+        std::fs::write(&input, SQUAD)?;
+        let mut argv = vec![
+            "csv",
+            "-i",
+            input.to_str().unwrap(),
+            "-o",
+            output.to_str().unwrap(),
+        ];
+        argv.extend_from_slice(args);
+        process_csv(&CsvOpts::parse_from(argv))?;
+        Ok((dir, output))
+    }
+
+    #[test]
+    fn test_parquet_round_trip() -> anyhow::Result<()> {
+        let (_dir, output) = convert(&[
+            "--format",
+            "parquet",
+            "--row-group-size",
+            "2",
+            "--compression",
+            "zstd",
+        ])?;
+        let builder = ParquetRecordBatchReaderBuilder::try_new(File::open(output)?)?;
+        let metadata = builder.metadata().clone();
+        assert_eq!(metadata.num_row_groups(), 2);
+        assert!(matches!(
+            metadata.row_group(0).column(0).compression(),
+            basic::Compression::ZSTD(_)
+        ));
+        let schema = builder.schema().clone();
+        let types = schema
+            .fields()
+            .iter()
+            .map(|f| f.data_type().clone())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            types,
+            [
+                DataType::Utf8,
+                DataType::Int64,
+                DataType::Float64,
+                DataType::Boolean,
+                DataType::Utf8
+            ]
+        );
+        let batches = builder.build()?.collect::<Result<Vec<_>, _>>()?;
+        let kits = batches
+            .iter()
+            .flat_map(|b| b.column(1).as_primitive::<Int64Type>().values().to_vec())
+            .collect::<Vec<_>>();
+        assert_eq!(kits, [77, 1, 3]);
+        assert!(batches[0].column(2).is_null(2));
+        Ok(())
+    }
+
+    #[test]
+    fn test_arrow_round_trip() -> anyhow::Result<()> {
+        let (_dir, output) = convert(&["--format", "arrow", "--compression", "lz4"])?;
+        let reader = FileReader::try_new(File::open(output)?, None)?;
+        let batches = reader.collect::<Result<Vec<_>, _>>()?;
+        assert_eq!(batches.len(), 1);
+        assert_eq!(batches[0].num_rows(), 3);
+        assert_eq!(
+            batches[0].column(0).as_string::<i32>().value(2),
+            "Chiellini"
+        );
+        assert!(batches[0].column(3).as_boolean().value(2));
+
+        assert!(convert(&["--format", "arrow", "--compression", "gzip"]).is_err());
+        let err = convert(&["--format", "parquet", "--select", "Name,Height,Name"]).unwrap_err();
+        assert!(
+            err.to_string().starts_with("Column \"Name\" appears twice"),
+            "{}",
+            err
+        );
+        convert(&[
+            "--format",
+            "parquet",
+            "--select",
+            "Name,Height,Name",
+            "--rename",
+            "Name=Player",
+        ])?;
+        // a cell past the inferred rows that does not fit its column
+        let mut kits = ColumnBuilder::new(ColumnType::Integer);
+        assert!(kits.append(&Value::from(7)));
+        assert!(!kits.append(&Value::from("7x")));
+        Ok(())
+    }
+}
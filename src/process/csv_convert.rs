@@ -1,9 +1,23 @@
-use csv::Reader;
+use csv::StringRecord;
+use serde_json::Value;
 // use serde::{Deserialize, Serialize};
 
-use std::fs;
+use rayon::prelude::*;
+use std::{collections::HashSet, io::Write};
 
-use crate::cli::OutputFormat;
+use crate::{
+    cli::{CsvOpts, OutputFormat},
+    reader_from_input, writer_for_output,
+};
+
+use super::{
+    csv_columnar::ColumnarWriter,
+    csv_filter::{column_index, Expr},
+    csv_infer::{infer_column_types, load_column_schema, ColumnType},
+    csv_nested::{flatten, NestedLayout},
+    csv_reader::CsvSource,
+    csv_writer::{csv_output, encode_record, value_to_cell, RecordWriter},
+};
 // #[derive(Debug, Serialize, Deserialize)]
 // #[serde(rename_all = "PascalCase")]
 // struct Player {
@@ -17,25 +31,427 @@ use crate::cli::OutputFormat;
 //     #[serde(rename = "Kit Number")]
 //     kit: u8,
 // }
-pub fn process_csv(input: &str, output: &str, format: &OutputFormat) -> anyhow::Result<()> {
-    let mut reader = Reader::from_path(input)?;
-    let mut ret = Vec::with_capacity(128);
-    let headers = reader.headers()?.clone();
-    // let mut ret = Vec::with_capacity(128);
-    // let records= reader.deserialize().map(|record| record.unwrap()).collect::<Vec<Player>>();
-    for result in reader.records() {
-        let record = result?;
-        let json_value = headers
+/// records handed to the thread pool at a time, the next batch is read while
+/// this one is converted
+const BATCH_ROWS: usize = 16 * 1024;
+
+pub fn process_csv(opts: &CsvOpts) -> anyhow::Result<()> {
+    let mut reader = CsvSource::open(&opts.input, &opts.reader)?;
+
+    // sample the head of the file for type inference, the rest is streamed
+    let mut sample = Vec::new();
+    let mut record = StringRecord::new();
+    if !opts.no_infer {
+        while sample.len() < opts.infer_rows && reader.read_record(&mut record)? {
+            sample.push(record.clone());
+        }
+    }
+    let converter = RecordConverter::new(opts, &reader, &sample)?;
+
+    if matches!(opts.format, OutputFormat::Parquet | OutputFormat::Arrow) {
+        let mut writer = ColumnarWriter::new(opts, &converter.names, &converter.types)?;
+        convert_each(&mut reader, &sample, &converter, |value| {
+            writer.write(value)
+        })?;
+        return writer.finish();
+    }
+
+    let mut writer = RecordWriter::new(
+        writer_for_output(opts.output.as_deref())?,
+        opts.format,
+        converter.names.clone(),
+    );
+    if opts.threads == 1 {
+        convert_each(&mut reader, &sample, &converter, |value| {
+            writer.write(value)
+        })?;
+    } else {
+        convert_parallel(opts, &converter, &mut reader, sample, &mut writer)?;
+    }
+    writer.finish()?;
+    Ok(())
+}
+
+/// Pass the sampled records, then the rest of the input, through the converter
+fn convert_each(
+    reader: &mut CsvSource,
+    sample: &[StringRecord],
+    converter: &RecordConverter,
+    mut emit: impl FnMut(&Value) -> anyhow::Result<()>,
+) -> anyhow::Result<()> {
+    for record in sample {
+        if let Some(value) = converter.convert(record)? {
+            emit(&value)?;
+        }
+    }
+    // reuse one record buffer so memory stays flat however large the input is
+    let mut record = StringRecord::new();
+    while reader.read_record(&mut record)? {
+        if let Some(value) = converter.convert(&record)? {
+            emit(&value)?;
+        }
+    }
+    Ok(())
+}
+
+/// Convert and encode batches of records on a thread pool, the encoded records
+/// are written in input order. Parsing is serial: the next batch is read on
+/// this thread while the pool encodes the current one, so a run takes at
+/// least as long as parsing the input alone (the `parse` case of the csv bench).
+fn convert_parallel<W: Write>(
+    opts: &CsvOpts,
+    converter: &RecordConverter,
+    reader: &mut CsvSource,
+    sample: Vec<StringRecord>,
+    writer: &mut RecordWriter<W>,
+) -> anyhow::Result<()> {
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(opts.threads)
+        .build()?;
+    let mut batch = sample;
+    loop {
+        let mut encoded = Ok(Vec::new());
+        // the reader stays on this thread, only the conversion moves to the pool
+        let next = pool.in_place_scope(|scope| {
+            scope.spawn(|_| encoded = encode_batch(converter, opts.format, &batch));
+            read_batch(reader)
+        })?;
+        for record in encoded?.iter().flatten() {
+            writer.write_encoded(record)?;
+        }
+        if next.is_empty() {
+            return Ok(());
+        }
+        batch = next;
+    }
+}
+
+fn read_batch(reader: &mut CsvSource) -> anyhow::Result<Vec<StringRecord>> {
+    let mut batch = Vec::with_capacity(BATCH_ROWS);
+    let mut record = StringRecord::new();
+    while batch.len() < BATCH_ROWS && reader.read_record(&mut record)? {
+        batch.push(std::mem::take(&mut record));
+    }
+    Ok(batch)
+}
+
+/// records dropped by `--where` are `None`
+fn encode_batch(
+    converter: &RecordConverter,
+    format: OutputFormat,
+    batch: &[StringRecord],
+) -> anyhow::Result<Vec<Option<Vec<u8>>>> {
+    batch
+        .par_iter()
+        .with_min_len(256)
+        .map(|record| {
+            let Some(value) = converter.convert(record)? else {
+                return Ok(None);
+            };
+            let mut buf = Vec::with_capacity(256);
+            encode_record(format, &converter.names, &value, &mut buf)?;
+            Ok(Some(buf))
+        })
+        .collect()
+}
+
+/// Turns raw records into output values: rows are filtered with `--where` on
+/// the original columns, then projected with `--select`/`--rename` and typed.
+struct RecordConverter {
+    filter: Option<Expr>,
+    /// input column of every output column
+    columns: Vec<usize>,
+    names: Vec<String>,
+    types: Vec<ColumnType>,
+    arrays: bool,
+    /// with `--nested`, how the output names map to nested fields
+    nested: Option<NestedLayout>,
+}
+
+impl RecordConverter {
+    fn new(opts: &CsvOpts, reader: &CsvSource, sample: &[StringRecord]) -> anyhow::Result<Self> {
+        let headers = &reader.headers;
+        let filter = opts
+            .filter
+            .as_deref()
+            .map(|expr| Expr::parse(expr, headers))
+            .transpose()?;
+        let columns = if opts.select.is_empty() {
+            (0..headers.len()).collect()
+        } else {
+            opts.select
+                .iter()
+                .map(|name| column_index(headers, name))
+                .collect::<anyhow::Result<Vec<_>>>()?
+        };
+        let mut names = columns
             .iter()
-            .zip(record.iter())
-            .collect::<serde_json::Value>();
-        ret.push(json_value);
+            .map(|&i| headers[i].clone())
+            .collect::<Vec<_>>();
+        for (old, new) in &opts.rename {
+            let i = column_index(&names, old)?;
+            if names
+                .iter()
+                .enumerate()
+                .any(|(j, name)| j != i && name == new)
+            {
+                anyhow::bail!(
+                    "Cannot rename {:?} to {:?}, another output column has that name",
+                    old,
+                    new
+                );
+            }
+            names[i] = new.clone();
+        }
+        let types = column_types(opts, reader, sample)?;
+        let types = columns.iter().map(|&i| types[i]).collect();
+        let nested = if opts.nested {
+            if !matches!(
+                opts.format,
+                OutputFormat::Json | OutputFormat::Yaml | OutputFormat::Ndjson | OutputFormat::Toml
+            ) {
+                anyhow::bail!("--nested needs json, yaml, ndjson or toml output");
+            }
+            Some(NestedLayout::new(&names)?)
+        } else {
+            None
+        };
+        Ok(Self {
+            filter,
+            columns,
+            names,
+            types,
+            arrays: opts.arrays,
+            nested,
+        })
     }
-    let json = match format {
-        OutputFormat::Json => serde_json::to_string_pretty(&ret)?,
-        OutputFormat::Yaml => serde_yaml::to_string(&ret)?,
+
+    fn convert(&self, record: &StringRecord) -> anyhow::Result<Option<Value>> {
+        if let Some(filter) = &self.filter {
+            if !filter.eval(record) {
+                return Ok(None);
+            }
+        }
+        let cells = self
+            .columns
+            .iter()
+            .zip(&self.types)
+            .map(|(&i, t)| t.convert(record.get(i).unwrap_or_default()));
+        if self.arrays {
+            return Ok(Some(cells.collect()));
+        }
+        if let Some(nested) = &self.nested {
+            return nested.build(cells).map(Some);
+        }
+        Ok(Some(
+            self.names
+                .iter()
+                .cloned()
+                .zip(cells)
+                .collect::<serde_json::Map<_, _>>()
+                .into(),
+        ))
+    }
+}
+
+/// explicit schema entries win over the cell types of a spreadsheet, which win
+/// over inferred types, columns without any stay strings
+fn column_types(
+    opts: &CsvOpts,
+    reader: &CsvSource,
+    sample: &[StringRecord],
+) -> anyhow::Result<Vec<ColumnType>> {
+    let headers = &reader.headers;
+    let mut types = match &reader.types {
+        _ if opts.no_infer => vec![ColumnType::String; headers.len()],
+        Some(types) => types.clone(),
+        None => infer_column_types(headers.len(), sample),
     };
+    // `--columns` may name more columns than the sheet has
+    types.resize(headers.len(), ColumnType::String);
+    if let Some(schema) = &opts.schema {
+        let schema = load_column_schema(schema)?;
+        for (name, t) in headers.iter().zip(types.iter_mut()) {
+            if let Some(explicit) = schema.get(name.as_str()) {
+                *t = *explicit;
+            }
+        }
+    }
+    Ok(types)
+}
 
-    fs::write(output, json)?;
+/// Convert an array of flat objects (json, yaml or ndjson) back to CSV. The header
+/// is the union of all keys in first-seen order, missing keys become empty cells.
+pub fn process_to_csv(opts: &CsvOpts) -> anyhow::Result<()> {
+    let from = opts
+        .from
+        .ok_or_else(|| anyhow::anyhow!("Input format is required"))?;
+    let content = reader_from_input(&opts.input)?;
+    let mut records = parse_records(&content, from)?;
+    if opts.nested {
+        records = records.iter().map(flatten).collect();
+    }
+
+    let mut headers = Vec::new();
+    let mut seen = HashSet::new();
+    for record in &records {
+        for key in record.keys() {
+            if seen.insert(key.as_str()) {
+                headers.push(key.as_str());
+            }
+        }
+    }
+
+    let mut writer = csv_output(opts.output.as_deref(), &opts.reader)?;
+    writer.write_record(&headers)?;
+    for record in &records {
+        writer.write_record(
+            headers
+                .iter()
+                .map(|key| record.get(*key).map(value_to_cell).unwrap_or_default()),
+        )?;
+    }
+    writer.flush()?;
     Ok(())
 }
+
+fn parse_records(
+    content: &str,
+    from: OutputFormat,
+) -> anyhow::Result<Vec<serde_json::Map<String, Value>>> {
+    let values = match from {
+        OutputFormat::Json => match serde_json::from_str(content)? {
+            Value::Array(values) => values,
+            value => vec![value],
+        },
+        OutputFormat::Yaml => match serde_yaml::from_str(content)? {
+            Value::Array(values) => values,
+            value => vec![value],
+        },
+        OutputFormat::Ndjson => content
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(serde_json::from_str)
+            .collect::<Result<_, _>>()?,
+        // the array of tables written by the toml output, whatever its name
+        OutputFormat::Toml => {
+            let table: serde_json::Map<String, Value> = toml::from_str(content)?;
+            match table.into_iter().next() {
+                Some((_, Value::Array(values))) => values,
+                Some(_) => anyhow::bail!("Expected an array of tables"),
+                None => vec![],
+            }
+        }
+        OutputFormat::Markdown
+        | OutputFormat::Html
+        | OutputFormat::Csv
+        | OutputFormat::Parquet
+        | OutputFormat::Arrow => {
+            anyhow::bail!("Cannot convert {} back to CSV", from)
+        }
+    };
+    values
+        .into_iter()
+        .enumerate()
+        .map(|(i, value)| match value {
+            Value::Object(map) => Ok(map),
+            _ => Err(anyhow::anyhow!("Record {} is not an object", i + 1)),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_records() {
+        let json = parse_records(r#"[{"b": 1, "a": "x"}, {"c": null}]"#, OutputFormat::Json);
+        let ndjson = parse_records(
+            "{\"b\": 1, \"a\": \"x\"}\n\n{\"c\": null}\n",
+            OutputFormat::Ndjson,
+        );
+        let yaml = parse_records("- b: 1\n  a: x\n- c: null\n", OutputFormat::Yaml);
+        let toml = parse_records(
+            "[[records]]\nb = 1\na = \"x\"\n\n[[records]]\nc = \"\"\n",
+            OutputFormat::Toml,
+        );
+        for records in [json, ndjson, yaml, toml] {
+            let records = records.unwrap();
+            assert_eq!(records.len(), 2);
+            assert_eq!(records[0].keys().collect::<Vec<_>>(), ["b", "a"]);
+            assert_eq!(value_to_cell(&records[0]["b"]), "1");
+            assert_eq!(value_to_cell(&records[1]["c"]), "");
+        }
+        assert!(parse_records("[1, 2]", OutputFormat::Json).is_err());
+    }
+
+    #[test]
+    fn test_threads_keep_order() -> anyhow::Result<()> {
+        use clap::Parser;
+
+        let dir = tempfile::tempdir()?;
+        let input = dir.path().join("in.csv");
+        let mut content = String::from("id,name\n");
+        for i in 0..BATCH_ROWS * 2 + 10 {
+            content.push_str(&format!("{},player {}\n", i, i));
+        }
+        std::fs::write(&input, content)?;
+        let run = |threads: &str| -> anyhow::Result<String> {
+            let output = dir.path().join(format!("out{}.json", threads));
+            let opts = CsvOpts::parse_from([
+                "csv",
+                "-i",
+                input.to_str().unwrap(),
+                "-o",
+                output.to_str().unwrap(),
+                "--where",
+                "id > 5",
+                "--infer-rows",
+                "3",
+                "--threads",
+                threads,
+            ]);
+            process_csv(&opts)?;
+            Ok(std::fs::read_to_string(output)?)
+        };
+        let single = run("1")?;
+        assert!(single.starts_with("[\n  {\n    \"id\": 6,"));
+        assert_eq!(run("4")?, single);
+        Ok(())
+    }
+
+    #[test]
+    fn test_rename_collision() -> anyhow::Result<()> {
+        use clap::Parser;
+
+        let dir = tempfile::tempdir()?;
+        let input = dir.path().join("in.csv");
+        std::fs::write(&input, "a,b,c\n1,2,3\n")?;
+        let output = dir.path().join("out.ndjson");
+        let run = |rename: &str| {
+            process_csv(&CsvOpts::parse_from([
+                "csv",
+                "-i",
+                input.to_str().unwrap(),
+                "-o",
+                output.to_str().unwrap(),
+                "--format",
+                "ndjson",
+                "--rename",
+                rename,
+            ]))
+        };
+        assert!(run("a=b").is_err());
+        assert!(run("a=x,c=x").is_err());
+        // renames apply in order, a swap goes through a free name
+        assert!(run("a=b,b=a").is_err());
+        run("a=x,b=a,x=b")?;
+        assert_eq!(
+            std::fs::read_to_string(&output)?,
+            "{\"b\":1,\"a\":2,\"c\":3}\n"
+        );
+        run("a=a")?;
+        Ok(())
+    }
+}
@@ -0,0 +1,314 @@
+use std::{cmp::Ordering, io::Write};
+
+use csv::StringRecord;
+use serde::Serialize;
+use serde_json::{Map, Value};
+
+use crate::{
+    cli::{CsvDiffOpts, DiffFormat, OutputFormat},
+    writer_for_output,
+};
+
+use super::{
+    csv_filter::column_index,
+    csv_reader::CsvSource,
+    csv_sort::{compare_keys, external_sort, key_order, SortedRecords},
+    csv_writer::{encode_csv_row, RecordWriter},
+    table::render_table,
+};
+
+/// One difference between the old and the new file, rows are matched by key
+#[derive(Debug, Serialize, PartialEq)]
+#[serde(tag = "op", rename_all = "lowercase")]
+pub enum RowChange {
+    Added {
+        key: Map<String, Value>,
+        row: Map<String, Value>,
+    },
+    Removed {
+        key: Map<String, Value>,
+        row: Map<String, Value>,
+    },
+    Changed {
+        key: Map<String, Value>,
+        fields: Vec<FieldChange>,
+    },
+}
+
+#[derive(Debug, Serialize, PartialEq)]
+pub struct FieldChange {
+    pub column: String,
+    pub old: String,
+    pub new: String,
+}
+
+/// One side of the diff, sorted by its key columns
+struct DiffSide {
+    headers: Vec<String>,
+    key: Vec<usize>,
+    records: SortedRecords,
+    head: Option<StringRecord>,
+}
+
+pub fn process_csv_diff(opts: &CsvDiffOpts) -> anyhow::Result<()> {
+    let max_bytes = opts.memory_limit * 1024 * 1024;
+    let mut old = DiffSide::open(&opts.old, opts, max_bytes)?;
+    let mut new = DiffSide::open(&opts.new, opts, max_bytes)?;
+
+    let mut writer = writer_for_output(opts.output.as_deref())?;
+    match opts.format {
+        DiffFormat::Json => {
+            let mut records = RecordWriter::new(writer, OutputFormat::Json, vec![]);
+            diff_sides(&mut old, &mut new, |change| {
+                records.write(&serde_json::to_value(&change)?)
+            })?;
+            records.finish()?;
+        }
+        DiffFormat::Patch => {
+            writeln!(writer, "--- {}\n+++ {}", opts.old, opts.new)?;
+            diff_sides(&mut old, &mut new, |change| {
+                writer.write_all(patch_hunk(&change)?.as_bytes())?;
+                Ok(())
+            })?;
+            writer.flush()?;
+        }
+        DiffFormat::Table => {
+            let mut rows = Vec::new();
+            diff_sides(&mut old, &mut new, |change| {
+                rows.extend(table_rows(&change)?);
+                Ok(())
+            })?;
+            let headers = ["op", "key", "column", "old", "new"].map(String::from);
+            writer.write_all(render_table(&headers, &rows).as_bytes())?;
+            writer.flush()?;
+        }
+    }
+    Ok(())
+}
+
+impl DiffSide {
+    fn open(input: &str, opts: &CsvDiffOpts, max_bytes: usize) -> anyhow::Result<Self> {
+        let mut reader = CsvSource::open(input, &opts.reader)?;
+        let key = opts
+            .key
+            .iter()
+            .map(|name| column_index(&reader.headers, name))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        let mut records = external_sort(&mut reader, max_bytes, key_order(key.clone()))?;
+        let head = records.next().transpose()?;
+        Ok(Self {
+            headers: reader.headers,
+            key,
+            records,
+            head,
+        })
+    }
+
+    fn advance(&mut self) -> anyhow::Result<StringRecord> {
+        let next = self.records.next().transpose()?;
+        Ok(std::mem::replace(&mut self.head, next).unwrap_or_default())
+    }
+
+    fn key_map(&self, record: &StringRecord) -> Map<String, Value> {
+        self.key
+            .iter()
+            .map(|&i| (self.headers[i].clone(), cell(record, i)))
+            .collect()
+    }
+
+    fn row_map(&self, record: &StringRecord) -> Map<String, Value> {
+        self.headers
+            .iter()
+            .enumerate()
+            .map(|(i, name)| (name.clone(), cell(record, i)))
+            .collect()
+    }
+}
+
+fn cell(record: &StringRecord, i: usize) -> Value {
+    Value::String(record.get(i).unwrap_or_default().to_string())
+}
+
+/// Merge join both key sorted sides, rows with a key on one side only are
+/// added or removed, rows with the same key are compared column by column
+fn diff_sides(
+    old: &mut DiffSide,
+    new: &mut DiffSide,
+    mut emit: impl FnMut(RowChange) -> anyhow::Result<()>,
+) -> anyhow::Result<()> {
+    // columns are matched by name, a column missing on one side reads as empty
+    let mut columns = old
+        .headers
+        .iter()
+        .enumerate()
+        .map(|(i, name)| {
+            (
+                name.clone(),
+                Some(i),
+                new.headers.iter().position(|h| h == name),
+            )
+        })
+        .collect::<Vec<_>>();
+    for (j, name) in new.headers.iter().enumerate() {
+        if !old.headers.contains(name) {
+            columns.push((name.clone(), None, Some(j)));
+        }
+    }
+
+    loop {
+        let ordering = match (&old.head, &new.head) {
+            (None, None) => return Ok(()),
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (Some(a), Some(b)) => compare_keys(a, &old.key, b, &new.key),
+        };
+        match ordering {
+            Ordering::Less => {
+                let record = old.advance()?;
+                emit(RowChange::Removed {
+                    key: old.key_map(&record),
+                    row: old.row_map(&record),
+                })?;
+            }
+            Ordering::Greater => {
+                let record = new.advance()?;
+                emit(RowChange::Added {
+                    key: new.key_map(&record),
+                    row: new.row_map(&record),
+                })?;
+            }
+            Ordering::Equal => {
+                let before = old.advance()?;
+                let after = new.advance()?;
+                let get = |record: &StringRecord, i: Option<usize>| {
+                    i.and_then(|i| record.get(i))
+                        .unwrap_or_default()
+                        .to_string()
+                };
+                let fields = columns
+                    .iter()
+                    .map(|(column, i, j)| FieldChange {
+                        column: column.clone(),
+                        old: get(&before, *i),
+                        new: get(&after, *j),
+                    })
+                    .filter(|f| f.old != f.new)
+                    .collect::<Vec<_>>();
+                if !fields.is_empty() {
+                    emit(RowChange::Changed {
+                        key: old.key_map(&before),
+                        fields,
+                    })?;
+                }
+            }
+        }
+    }
+}
+
+fn key_text(key: &Map<String, Value>) -> String {
+    key.iter()
+        .map(|(name, value)| format!("{}={}", name, value.as_str().unwrap_or_default()))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// the row as one CSV line without the line break
+fn row_text(row: &Map<String, Value>) -> anyhow::Result<String> {
+    let mut buf = Vec::new();
+    encode_csv_row(
+        row.values()
+            .map(|v| v.as_str().unwrap_or_default().to_string()),
+        &mut buf,
+    )?;
+    Ok(String::from_utf8(buf)?.trim_end().to_string())
+}
+
+/// `-`/`+` lines for removed and added rows, `~` with per field lines for changes
+fn patch_hunk(change: &RowChange) -> anyhow::Result<String> {
+    Ok(match change {
+        RowChange::Removed { key, row } => format!("- {}: {}\n", key_text(key), row_text(row)?),
+        RowChange::Added { key, row } => format!("+ {}: {}\n", key_text(key), row_text(row)?),
+        RowChange::Changed { key, fields } => {
+            let mut hunk = format!("~ {}\n", key_text(key));
+            for field in fields {
+                hunk.push_str(&format!("  - {}: {}\n", field.column, field.old));
+                hunk.push_str(&format!("  + {}: {}\n", field.column, field.new));
+            }
+            hunk
+        }
+    })
+}
+
+fn table_rows(change: &RowChange) -> anyhow::Result<Vec<Vec<String>>> {
+    Ok(match change {
+        RowChange::Removed { key, row } => {
+            vec![vec![
+                "removed".into(),
+                key_text(key),
+                String::new(),
+                row_text(row)?,
+                String::new(),
+            ]]
+        }
+        RowChange::Added { key, row } => {
+            vec![vec![
+                "added".into(),
+                key_text(key),
+                String::new(),
+                String::new(),
+                row_text(row)?,
+            ]]
+        }
+        RowChange::Changed { key, fields } => fields
+            .iter()
+            .map(|f| {
+                vec![
+                    "changed".into(),
+                    key_text(key),
+                    f.column.clone(),
+                    f.old.clone(),
+                    f.new.clone(),
+                ]
+            })
+            .collect(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    #[test]
+    fn test_diff_sides() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let old = dir.path().join("old.csv");
+        let new = dir.path().join("new.csv");
+        std::fs::write(&old, "ID,Name,Kit\n3,Buffon,77\n1,Szczesny,1\n2,Perin,37\n")?;
+        std::fs::write(&new, "ID,Kit,Name\n1,1,Szczesny\n3,1,Buffon\n4,8,Ramsey\n")?;
+        let opts = CsvDiffOpts::parse_from([
+            "diff",
+            old.to_str().unwrap(),
+            new.to_str().unwrap(),
+            "--key",
+            "ID",
+        ]);
+        // a small memory limit makes both sides spill
+        let mut old = DiffSide::open(&opts.old, &opts, 16)?;
+        let mut new = DiffSide::open(&opts.new, &opts, 16)?;
+        let mut changes = Vec::new();
+        diff_sides(&mut old, &mut new, |change| {
+            changes.push(patch_hunk(&change)?);
+            Ok(())
+        })?;
+        assert_eq!(
+            changes,
+            [
+                "- ID=2: 2,Perin,37\n",
+                "~ ID=3\n  - Kit: 77\n  + Kit: 1\n",
+                "+ ID=4: 4,8,Ramsey\n",
+            ]
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,167 @@
+use std::io::{self, Cursor, Read};
+
+use chardetng::EncodingDetector;
+use encoding_rs::{Decoder, DecoderResult, Encoding, UTF_8};
+
+/// how much of the input is looked at to guess its encoding
+const DETECT_SIZE: usize = 64 * 1024;
+const BUFFER_SIZE: usize = 16 * 1024;
+
+/// Wrap `input` so it reads as UTF-8. `label` is an encoding name such as
+/// `windows-1252`, `latin1` or `utf-16le`, or `auto` to look for a byte order
+/// mark and otherwise guess from the head of the input.
+pub fn utf8_reader(mut input: Box<dyn Read>, label: &str) -> anyhow::Result<Box<dyn Read>> {
+    if !label.eq_ignore_ascii_case("auto") {
+        let encoding = Encoding::for_label(label.as_bytes())
+            .ok_or_else(|| anyhow::anyhow!("Unknown encoding {:?}", label))?;
+        return Ok(Box::new(DecodeReader::new(
+            input,
+            encoding.new_decoder_with_bom_removal(),
+        )));
+    }
+
+    let mut prefix = Vec::with_capacity(DETECT_SIZE);
+    input
+        .by_ref()
+        .take(DETECT_SIZE as u64)
+        .read_to_end(&mut prefix)?;
+    let encoding = detect_encoding(&prefix, prefix.len() < DETECT_SIZE);
+    let input = Box::new(Cursor::new(prefix).chain(input));
+    // the decoder drops a byte order mark and follows it if it names another encoding
+    Ok(Box::new(DecodeReader::new(input, encoding.new_decoder())))
+}
+
+/// A byte order mark wins, then UTF-8 if the head is valid UTF-8, then the
+/// statistical guess
+pub fn detect_encoding(prefix: &[u8], complete: bool) -> &'static Encoding {
+    if let Some((encoding, _)) = Encoding::for_bom(prefix) {
+        return encoding;
+    }
+    match std::str::from_utf8(prefix) {
+        Ok(_) => return UTF_8,
+        // a multi byte character cut off at the end of the prefix is fine
+        Err(e) if e.error_len().is_none() && !complete => return UTF_8,
+        Err(_) => {}
+    }
+    let mut detector = EncodingDetector::new();
+    detector.feed(prefix, complete);
+    detector.guess(None, true)
+}
+
+/// Streams the UTF-8 decoding of another reader, failing on the first byte
+/// sequence that is not valid in the source encoding
+pub struct DecodeReader<R> {
+    inner: R,
+    decoder: Decoder,
+    input: Vec<u8>,
+    start: usize,
+    end: usize,
+    output: Vec<u8>,
+    out_start: usize,
+    out_end: usize,
+    /// bytes of `inner` decoded so far
+    offset: u64,
+    eof: bool,
+    done: bool,
+}
+
+impl<R: Read> DecodeReader<R> {
+    pub fn new(inner: R, decoder: Decoder) -> Self {
+        Self {
+            inner,
+            decoder,
+            input: vec![0; BUFFER_SIZE],
+            start: 0,
+            end: 0,
+            output: vec![0; BUFFER_SIZE * 3],
+            out_start: 0,
+            out_end: 0,
+            offset: 0,
+            eof: false,
+            done: false,
+        }
+    }
+}
+
+impl<R: Read> Read for DecodeReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            if self.out_start < self.out_end {
+                let n = buf.len().min(self.out_end - self.out_start);
+                buf[..n].copy_from_slice(&self.output[self.out_start..self.out_start + n]);
+                self.out_start += n;
+                return Ok(n);
+            }
+            if self.done {
+                return Ok(0);
+            }
+            if self.start == self.end && !self.eof {
+                self.start = 0;
+                self.end = self.inner.read(&mut self.input)?;
+                self.eof = self.end == 0;
+            }
+
+            let (result, read, written) = self.decoder.decode_to_utf8_without_replacement(
+                &self.input[self.start..self.end],
+                &mut self.output,
+                self.eof,
+            );
+            self.start += read;
+            self.offset += read as u64;
+            self.out_start = 0;
+            self.out_end = written;
+            match result {
+                DecoderResult::InputEmpty if self.eof => self.done = true,
+                DecoderResult::InputEmpty | DecoderResult::OutputFull => {}
+                DecoderResult::Malformed(bad, after) => {
+                    let at = self.offset.saturating_sub(bad as u64 + after as u64);
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!(
+                            "invalid {} byte sequence at byte offset {}, name the input encoding with --encoding",
+                            self.decoder.encoding().name(),
+                            at
+                        ),
+                    ));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode(bytes: &[u8], label: &str) -> anyhow::Result<String> {
+        let mut text = String::new();
+        utf8_reader(Box::new(Cursor::new(bytes.to_vec())), label)?.read_to_string(&mut text)?;
+        Ok(text)
+    }
+
+    #[test]
+    fn test_decode() -> anyhow::Result<()> {
+        // "Città;Perù" in windows-1252
+        let latin = b"Citt\xe0;Per\xf9\n";
+        assert_eq!(decode(latin, "windows-1252")?, "Città;Perù\n");
+        assert_eq!(decode(latin, "auto")?, "Città;Perù\n");
+        // utf-16 with a byte order mark
+        let utf16 = [0xff, 0xfe, b'a', 0, b',', 0, 0xe0, 0];
+        assert_eq!(decode(&utf16, "auto")?, "a,à");
+        assert_eq!(decode("\u{feff}a,b".as_bytes(), "auto")?, "a,b");
+        assert!(decode(b"a", "klingon").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_error_offset() {
+        let mut bytes = b"a,b\n".repeat(DETECT_SIZE / 4);
+        bytes.extend_from_slice(b"caf\xe9\n");
+        let err = decode(&bytes, "auto").unwrap_err().to_string();
+        assert!(
+            err.contains(&format!("byte offset {}", DETECT_SIZE + 3)),
+            "{}",
+            err
+        );
+    }
+}
@@ -0,0 +1,196 @@
+use std::path::Path;
+
+use calamine::{open_workbook_auto, Data, Reader};
+use csv::{Position, StringRecord};
+
+use crate::cli::CsvReaderOpts;
+
+use super::csv_infer::ColumnType;
+
+const WORKBOOK_EXTENSIONS: [&str; 5] = ["xlsx", "xlsm", "xlsb", "xls", "ods"];
+
+/// One worksheet read as CSV would be: the header row, the data rows and the
+/// column types taken from the cells
+pub struct Sheet {
+    pub header_row: StringRecord,
+    pub rows: Vec<StringRecord>,
+    pub types: Vec<ColumnType>,
+}
+
+/// Spreadsheets are recognized by their extension, anything else is read as CSV
+pub fn is_workbook(input: &str) -> bool {
+    Path::new(input)
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| {
+            WORKBOOK_EXTENSIONS
+                .iter()
+                .any(|e| ext.eq_ignore_ascii_case(e))
+        })
+}
+
+/// Read the sheet picked with `--sheet` (a name or a 1-based index, the first
+/// sheet by default). Rows above `--header-row` are skipped.
+pub fn read_sheet(input: &str, opts: &CsvReaderOpts) -> anyhow::Result<Sheet> {
+    let mut workbook = open_workbook_auto(input)?;
+    let names = workbook.sheet_names();
+    let name = match opts.sheet.as_deref() {
+        None => names.first(),
+        Some(sheet) => names.iter().find(|n| n.as_str() == sheet).or_else(|| {
+            sheet
+                .parse::<usize>()
+                .ok()
+                .and_then(|i| names.get(i.checked_sub(1)?))
+        }),
+    };
+    let Some(name) = name.cloned() else {
+        anyhow::bail!(
+            "No sheet {:?} in {}, sheets are: {}",
+            opts.sheet.as_deref().unwrap_or_default(),
+            input,
+            names.join(", ")
+        );
+    };
+    let range = workbook.worksheet_range(&name)?;
+
+    // sheet rows are numbered from 1 like in the spreadsheet, the range starts
+    // at the first row holding a value
+    let first_row = opts.header_row.unwrap_or(1).max(1);
+    let start = range.start().map_or(1, |(row, _)| row as usize + 1);
+    let mut rows = range
+        .rows()
+        .enumerate()
+        .map(|(i, cells)| (start + i, cells))
+        .skip_while(|(n, _)| *n < first_row)
+        .peekable();
+
+    let mut header_row = StringRecord::new();
+    if opts.header {
+        if let Some((_, cells)) = rows.next_if(|(n, _)| *n == first_row) {
+            header_row = cells.iter().map(cell_text).collect();
+        }
+    }
+    let mut types = vec![None; range.width()];
+    let rows = rows
+        .map(|(n, cells)| {
+            for (t, cell) in types.iter_mut().zip(cells) {
+                *t = fold_type(*t, cell);
+            }
+            let mut record = cells.iter().map(cell_text).collect::<StringRecord>();
+            let mut position = Position::new();
+            position.set_line(n as u64).set_record(n as u64);
+            record.set_position(Some(position));
+            record
+        })
+        .collect();
+    Ok(Sheet {
+        header_row,
+        rows,
+        // a column without any value is a string column, as with CSV
+        types: types
+            .into_iter()
+            .map(|t| t.unwrap_or(ColumnType::String))
+            .collect(),
+    })
+}
+
+/// Cell text as it would appear in a CSV export, dates in ISO 8601
+fn cell_text(cell: &Data) -> String {
+    match cell {
+        Data::Empty => String::new(),
+        Data::String(s) | Data::DateTimeIso(s) | Data::DurationIso(s) => s.clone(),
+        Data::Int(i) => i.to_string(),
+        Data::Float(f) => match as_integer(*f) {
+            Some(i) => i.to_string(),
+            None => f.to_string(),
+        },
+        Data::Bool(b) => b.to_string(),
+        Data::DateTime(dt) => match dt.as_datetime() {
+            Some(dt) if dt.time() == chrono::NaiveTime::MIN => dt.format("%Y-%m-%d").to_string(),
+            Some(dt) => dt.format("%Y-%m-%dT%H:%M:%S").to_string(),
+            // durations have no calendar date, keep the number of days
+            None => dt.as_f64().to_string(),
+        },
+        Data::Error(e) => e.to_string(),
+    }
+}
+
+/// spreadsheets store every number as a float, whole ones are read as integers
+fn as_integer(f: f64) -> Option<i64> {
+    (f.fract() == 0.0 && f.abs() < i64::MAX as f64).then_some(f as i64)
+}
+
+fn cell_type(cell: &Data) -> Option<ColumnType> {
+    match cell {
+        Data::Empty => None,
+        Data::Int(_) => Some(ColumnType::Integer),
+        Data::Float(f) if as_integer(*f).is_some() => Some(ColumnType::Integer),
+        Data::Float(_) => Some(ColumnType::Float),
+        Data::Bool(_) => Some(ColumnType::Boolean),
+        Data::DateTime(dt) if dt.is_datetime() => Some(ColumnType::Date),
+        Data::DateTimeIso(_) => Some(ColumnType::Date),
+        _ => Some(ColumnType::String),
+    }
+}
+
+/// the type of a column so far combined with one more cell, integers and
+/// floats make a float column and any other mix a string column
+fn fold_type(column: Option<ColumnType>, cell: &Data) -> Option<ColumnType> {
+    match (column, cell_type(cell)) {
+        (t, None) | (None, t) => t,
+        (Some(a), Some(b)) if a == b => Some(a),
+        (Some(ColumnType::Integer), Some(ColumnType::Float))
+        | (Some(ColumnType::Float), Some(ColumnType::Integer)) => Some(ColumnType::Float),
+        _ => Some(ColumnType::String),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    #[test]
+    fn test_read_sheet() -> anyhow::Result<()> {
+        let opts = CsvReaderOpts::parse_from(["csv", "--header-row", "2"]);
+        let sheet = read_sheet("assets/juventus.xlsx", &opts)?;
+        assert_eq!(
+            sheet.header_row,
+            vec!["Name", "Kit Number", "Height", "Captain", "DOB"]
+        );
+        assert_eq!(
+            sheet.rows[0],
+            vec!["Gianluigi Buffon", "77", "1.92", "false", "1978-01-28"]
+        );
+        assert_eq!(sheet.rows[0].position().map(|p| p.line()), Some(3));
+        assert_eq!(
+            sheet.types,
+            [
+                ColumnType::String,
+                ColumnType::Integer,
+                ColumnType::Float,
+                ColumnType::Boolean,
+                ColumnType::Date
+            ]
+        );
+
+        let opts = CsvReaderOpts::parse_from(["csv", "--sheet", "2", "--header", "false"]);
+        let sheet = read_sheet("assets/juventus.xlsx", &opts)?;
+        assert!(sheet.header_row.is_empty());
+        assert_eq!(sheet.rows[1], vec!["Coach", "Maurizio Sarri"]);
+
+        let opts = CsvReaderOpts::parse_from(["csv", "--sheet", "Bench"]);
+        assert!(read_sheet("assets/juventus.xlsx", &opts).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_fold_type() {
+        let float = fold_type(Some(ColumnType::Integer), &Data::Float(2.5));
+        assert_eq!(float, Some(ColumnType::Float));
+        let text = fold_type(float, &Data::Bool(true));
+        assert_eq!(text, Some(ColumnType::String));
+        assert_eq!(fold_type(None, &Data::Empty), None);
+        assert!(is_workbook("squad.XLSX") && !is_workbook("squad.csv"));
+    }
+}
@@ -0,0 +1,375 @@
+use std::cmp::Ordering;
+
+use csv::StringRecord;
+use regex::Regex;
+
+use super::csv_infer::finite_number;
+
+/// Token of the `--where` expression language
+#[derive(Debug, Clone, PartialEq)]
+pub enum Token {
+    /// bare word or `backticked` name, keywords are words too
+    Ident(String),
+    /// 'single' or "double" quoted string
+    Str(String),
+    Num(String),
+    Op(&'static str),
+    LParen,
+    RParen,
+    Comma,
+    Star,
+    Range,
+}
+
+const OPS: [&str; 10] = ["==", "!=", "<>", "<=", ">=", "!~", "=", "<", ">", "~"];
+
+pub fn tokenize(s: &str) -> anyhow::Result<Vec<Token>> {
+    let chars = s.chars().collect::<Vec<_>>();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if c.is_whitespace() {
+            i += 1;
+            continue;
+        }
+        let rest = chars[i..].iter().take(2).collect::<String>();
+        if rest == ".." {
+            tokens.push(Token::Range);
+            i += 2;
+        } else if let Some(op) = OPS.iter().find(|op| rest.starts_with(**op)) {
+            tokens.push(Token::Op(op));
+            i += op.len();
+        } else if matches!(c, '\'' | '"' | '`') {
+            let (text, end) = quoted(&chars, i)?;
+            tokens.push(if c == '`' {
+                Token::Ident(text)
+            } else {
+                Token::Str(text)
+            });
+            i = end;
+        } else if c.is_ascii_digit()
+            || (c == '-' && chars.get(i + 1).is_some_and(|c| c.is_ascii_digit()))
+        {
+            let start = i;
+            i += 1;
+            while i < chars.len()
+                && (chars[i].is_ascii_digit()
+                    || (chars[i] == '.' && chars.get(i + 1) != Some(&'.')))
+            {
+                i += 1;
+            }
+            tokens.push(Token::Num(chars[start..i].iter().collect()));
+        } else if c.is_alphanumeric() || c == '_' {
+            let start = i;
+            while i < chars.len() && (chars[i].is_alphanumeric() || matches!(chars[i], '_' | '.')) {
+                // a trailing `..` belongs to a range, not to the name
+                if chars[i] == '.' && chars.get(i + 1) == Some(&'.') {
+                    break;
+                }
+                i += 1;
+            }
+            tokens.push(Token::Ident(chars[start..i].iter().collect()));
+        } else {
+            tokens.push(match c {
+                '(' => Token::LParen,
+                ')' => Token::RParen,
+                ',' => Token::Comma,
+                '*' => Token::Star,
+                _ => anyhow::bail!("Unexpected character {:?} at {}", c, i),
+            });
+            i += 1;
+        }
+    }
+    Ok(tokens)
+}
+
+/// read a quoted run starting at `start`, a backslash escapes the next char
+fn quoted(chars: &[char], start: usize) -> anyhow::Result<(String, usize)> {
+    let quote = chars[start];
+    let mut text = String::new();
+    let mut i = start + 1;
+    while i < chars.len() {
+        match chars[i] {
+            '\\' if i + 1 < chars.len() => {
+                text.push(chars[i + 1]);
+                i += 2;
+            }
+            c if c == quote => return Ok((text, i + 1)),
+            c => {
+                text.push(c);
+                i += 1;
+            }
+        }
+    }
+    Err(anyhow::anyhow!("Unterminated {} at {}", quote, start))
+}
+
+/// Boolean expression evaluated on the raw cells of a record
+#[derive(Debug)]
+pub enum Expr {
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+    Compare(Operand, CmpOp, Operand),
+    Matches(Operand, Regex),
+    /// inclusive numeric range, `col in 1..10`
+    Range(Operand, f64, f64),
+}
+
+#[derive(Debug)]
+pub enum Operand {
+    Column(usize),
+    Literal(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CmpOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl Expr {
+    /// Parse a whole expression, column names are resolved against `headers`
+    pub fn parse(s: &str, headers: &[String]) -> anyhow::Result<Self> {
+        let tokens = tokenize(s)?;
+        let mut parser = ExprParser::new(&tokens, headers);
+        let expr = parser.parse_expr()?;
+        if let Some(token) = parser.peek() {
+            anyhow::bail!("Unexpected {:?} in expression", token);
+        }
+        Ok(expr)
+    }
+
+    pub fn eval(&self, record: &StringRecord) -> bool {
+        match self {
+            Expr::And(a, b) => a.eval(record) && b.eval(record),
+            Expr::Or(a, b) => a.eval(record) || b.eval(record),
+            Expr::Not(e) => !e.eval(record),
+            Expr::Compare(a, op, b) => {
+                let ordering = compare_cells(a.value(record), b.value(record));
+                match op {
+                    CmpOp::Eq => ordering == Ordering::Equal,
+                    CmpOp::Ne => ordering != Ordering::Equal,
+                    CmpOp::Lt => ordering == Ordering::Less,
+                    CmpOp::Le => ordering != Ordering::Greater,
+                    CmpOp::Gt => ordering == Ordering::Greater,
+                    CmpOp::Ge => ordering != Ordering::Less,
+                }
+            }
+            Expr::Matches(a, re) => re.is_match(a.value(record)),
+            Expr::Range(a, lo, hi) => {
+                finite_number(a.value(record)).is_some_and(|v| *lo <= v && v <= *hi)
+            }
+        }
+    }
+}
+
+impl Operand {
+    fn value<'a>(&'a self, record: &'a StringRecord) -> &'a str {
+        match self {
+            Operand::Column(i) => record.get(*i).unwrap_or(""),
+            Operand::Literal(s) => s,
+        }
+    }
+}
+
+/// Numbers compare numerically and sort before anything else, which compares
+/// as text, so the order stays total on mixed columns
+pub fn compare_cells(a: &str, b: &str) -> Ordering {
+    match (finite_number(a), finite_number(b)) {
+        (Some(x), Some(y)) => x.total_cmp(&y),
+        (Some(_), None) => Ordering::Less,
+        (None, Some(_)) => Ordering::Greater,
+        (None, None) => a.cmp(b),
+    }
+}
+
+pub fn column_index(headers: &[String], name: &str) -> anyhow::Result<usize> {
+    headers
+        .iter()
+        .position(|h| h == name)
+        .ok_or_else(|| anyhow::anyhow!("Unknown column {:?}", name))
+}
+
+/// Recursive descent parser over tokens. `or` binds loosest, then `and`, then `not`.
+pub struct ExprParser<'a> {
+    tokens: &'a [Token],
+    pos: usize,
+    headers: &'a [String],
+}
+
+impl<'a> ExprParser<'a> {
+    pub fn new(tokens: &'a [Token], headers: &'a [String]) -> Self {
+        Self {
+            tokens,
+            pos: 0,
+            headers,
+        }
+    }
+
+    pub fn peek(&self) -> Option<&'a Token> {
+        self.tokens.get(self.pos)
+    }
+
+    pub fn next(&mut self) -> Option<&'a Token> {
+        let token = self.tokens.get(self.pos);
+        self.pos += 1;
+        token
+    }
+
+    /// consume the keyword if it is next, keywords are case insensitive
+    pub fn keyword(&mut self, word: &str) -> bool {
+        match self.peek() {
+            Some(Token::Ident(s)) if s.eq_ignore_ascii_case(word) => {
+                self.pos += 1;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub fn expect(&mut self, token: &Token) -> anyhow::Result<()> {
+        match self.next() {
+            Some(t) if t == token => Ok(()),
+            t => Err(anyhow::anyhow!("Expected {:?}, found {:?}", token, t)),
+        }
+    }
+
+    pub fn parse_expr(&mut self) -> anyhow::Result<Expr> {
+        let mut expr = self.parse_and()?;
+        while self.keyword("or") {
+            expr = Expr::Or(Box::new(expr), Box::new(self.parse_and()?));
+        }
+        Ok(expr)
+    }
+
+    fn parse_and(&mut self) -> anyhow::Result<Expr> {
+        let mut expr = self.parse_not()?;
+        while self.keyword("and") {
+            expr = Expr::And(Box::new(expr), Box::new(self.parse_not()?));
+        }
+        Ok(expr)
+    }
+
+    fn parse_not(&mut self) -> anyhow::Result<Expr> {
+        if self.keyword("not") {
+            return Ok(Expr::Not(Box::new(self.parse_not()?)));
+        }
+        if self.peek() == Some(&Token::LParen) {
+            self.pos += 1;
+            let expr = self.parse_expr()?;
+            self.expect(&Token::RParen)?;
+            return Ok(expr);
+        }
+        self.parse_comparison()
+    }
+
+    fn parse_comparison(&mut self) -> anyhow::Result<Expr> {
+        let left = self.parse_operand()?;
+        if self.keyword("in") {
+            let lo = self.parse_number()?;
+            self.expect(&Token::Range)?;
+            let hi = self.parse_number()?;
+            return Ok(Expr::Range(left, lo, hi));
+        }
+        let op = match self.next() {
+            Some(Token::Op(op)) => *op,
+            t => anyhow::bail!("Expected a comparison, found {:?}", t),
+        };
+        if op == "~" || op == "!~" {
+            let pattern = match self.next() {
+                Some(Token::Str(s)) => Regex::new(s)?,
+                t => anyhow::bail!("Expected a regex string, found {:?}", t),
+            };
+            let matches = Expr::Matches(left, pattern);
+            return Ok(if op == "~" {
+                matches
+            } else {
+                Expr::Not(Box::new(matches))
+            });
+        }
+        let op = match op {
+            "=" | "==" => CmpOp::Eq,
+            "!=" | "<>" => CmpOp::Ne,
+            "<" => CmpOp::Lt,
+            "<=" => CmpOp::Le,
+            ">" => CmpOp::Gt,
+            _ => CmpOp::Ge,
+        };
+        Ok(Expr::Compare(left, op, self.parse_operand()?))
+    }
+
+    fn parse_operand(&mut self) -> anyhow::Result<Operand> {
+        match self.next() {
+            Some(Token::Ident(name)) => Ok(Operand::Column(column_index(self.headers, name)?)),
+            Some(Token::Str(s)) | Some(Token::Num(s)) => Ok(Operand::Literal(s.clone())),
+            t => Err(anyhow::anyhow!("Expected a column or value, found {:?}", t)),
+        }
+    }
+
+    fn parse_number(&mut self) -> anyhow::Result<f64> {
+        match self.next() {
+            Some(Token::Num(s)) => Ok(s.parse()?),
+            t => Err(anyhow::anyhow!("Expected a number, found {:?}", t)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eval(expr: &str, record: &[&str]) -> bool {
+        let headers = ["Name", "Nationality", "Kit Number"].map(String::from);
+        Expr::parse(expr, &headers)
+            .unwrap()
+            .eval(&StringRecord::from(record.to_vec()))
+    }
+
+    #[test]
+    fn test_expr_eval() {
+        let buffon = ["Gianluigi Buffon", "Italy", "77"];
+        assert!(eval("Nationality = 'Italy'", &buffon));
+        assert!(eval("`Kit Number` > 9", &buffon));
+        assert!(!eval("`Kit Number` in 1..30", &buffon));
+        assert!(eval(
+            "Name ~ '^Gian' and not Nationality != \"Italy\"",
+            &buffon
+        ));
+        assert!(eval(
+            "(Nationality = 'Poland' or `Kit Number` >= 77) and Name !~ 'x'",
+            &buffon
+        ));
+        assert!(eval("`Kit Number` in -1..77.5", &buffon));
+    }
+
+    #[test]
+    fn test_non_finite_cells() {
+        for cell in ["NaN", "nan", "inf", "-infinity"] {
+            let row = ["x", "Italy", cell];
+            assert_eq!(eval("`Kit Number` = 'NaN'", &row), cell == "NaN");
+            assert!(!eval("`Kit Number` in -1000..1000", &row));
+            assert!(eval("`Kit Number` > 99999", &row), "{}", cell);
+        }
+        assert!(!eval("Name = 'NaN'", &["nan", "Italy", "1"]));
+        assert!(eval("Name = 'NaN'", &["NaN", "Italy", "1"]));
+
+        let mut cells = ["abc", "10", "", "NaN", "9", "inf", "-1e3"];
+        cells.sort_by(|a, b| compare_cells(a, b));
+        assert_eq!(cells, ["-1e3", "9", "10", "", "NaN", "abc", "inf"]);
+    }
+
+    #[test]
+    fn test_expr_errors() {
+        let headers = ["Name".to_string()];
+        assert!(Expr::parse("Age > 3", &headers).is_err());
+        assert!(Expr::parse("Name = 'x", &headers).is_err());
+        assert!(Expr::parse("Name = 'x' Name", &headers).is_err());
+        assert!(Expr::parse("Name ~ '('", &headers).is_err());
+    }
+}
@@ -0,0 +1,233 @@
+use std::{
+    collections::HashMap,
+    fmt::{self, Display, Formatter},
+    fs,
+    str::FromStr,
+};
+
+use chrono::{DateTime, NaiveDate, NaiveDateTime};
+use csv::StringRecord;
+use serde::{Deserialize, Serialize};
+use serde_json::{Number, Value};
+
+/// Type of a CSV column, narrowest first in inference order
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ColumnType {
+    Boolean,
+    Integer,
+    Float,
+    /// ISO 8601 date or datetime, kept as a string in the output
+    Date,
+    String,
+}
+
+const INFER_ORDER: [ColumnType; 4] = [
+    ColumnType::Boolean,
+    ColumnType::Integer,
+    ColumnType::Float,
+    ColumnType::Date,
+];
+
+impl ColumnType {
+    /// whether a non-empty cell can be represented as this type
+    pub fn matches(&self, s: &str) -> bool {
+        match self {
+            ColumnType::Boolean => parse_bool(s).is_some(),
+            ColumnType::Integer => parse_int(s).is_some(),
+            ColumnType::Float => parse_float(s).is_some(),
+            ColumnType::Date => is_date(s),
+            ColumnType::String => true,
+        }
+    }
+
+    /// Convert a cell to json, empty cells become null and cells that do not
+    /// fit the column type are kept as strings
+    pub fn convert(&self, s: &str) -> Value {
+        if s.is_empty() {
+            return Value::Null;
+        }
+        let value = match self {
+            ColumnType::Boolean => parse_bool(s).map(Value::Bool),
+            ColumnType::Integer => parse_int(s).map(Value::from),
+            ColumnType::Float => parse_float(s).and_then(Number::from_f64).map(Value::Number),
+            ColumnType::Date | ColumnType::String => None,
+        };
+        value.unwrap_or_else(|| Value::String(s.to_string()))
+    }
+}
+
+fn parse_bool(s: &str) -> Option<bool> {
+    if s.eq_ignore_ascii_case("true") {
+        Some(true)
+    } else if s.eq_ignore_ascii_case("false") {
+        Some(false)
+    } else {
+        None
+    }
+}
+
+/// leading zeros mark identifiers (zip codes, ids) rather than numbers
+fn has_leading_zero(s: &str) -> bool {
+    let digits = s.strip_prefix(['-', '+']).unwrap_or(s).as_bytes();
+    digits.len() > 1 && digits[0] == b'0' && digits[1].is_ascii_digit()
+}
+
+fn parse_int(s: &str) -> Option<i64> {
+    if has_leading_zero(s) {
+        return None;
+    }
+    s.parse().ok()
+}
+
+fn parse_float(s: &str) -> Option<f64> {
+    if has_leading_zero(s) {
+        return None;
+    }
+    finite_number(s)
+}
+
+/// A cell read as a finite number. f64 also parses "inf" and "NaN", which are
+/// words rather than numbers here.
+pub fn finite_number(s: &str) -> Option<f64> {
+    if !s
+        .bytes()
+        .all(|b| b.is_ascii_digit() || matches!(b, b'.' | b'-' | b'+' | b'e' | b'E'))
+    {
+        return None;
+    }
+    s.parse::<f64>().ok().filter(|f| f.is_finite())
+}
+
+fn is_date(s: &str) -> bool {
+    NaiveDate::parse_from_str(s, "%Y-%m-%d").is_ok()
+        || NaiveDateTime::parse_from_str(s, "%Y-%m-%dT%H:%M:%S%.f").is_ok()
+        || NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M:%S%.f").is_ok()
+        || DateTime::parse_from_rfc3339(s).is_ok()
+}
+
+/// Infer the type of every column from a sample of records, a column is given
+/// the narrowest type all its non-empty cells fit
+pub fn infer_column_types(columns: usize, sample: &[StringRecord]) -> Vec<ColumnType> {
+    let mut inference = vec![TypeInference::default(); columns];
+    for record in sample {
+        for (column, cell) in inference.iter_mut().zip(record.iter()) {
+            column.update(cell);
+        }
+    }
+    inference.iter().map(TypeInference::column_type).collect()
+}
+
+/// Running type inference for one column, fed one cell at a time
+#[derive(Debug, Clone)]
+pub struct TypeInference {
+    candidates: Vec<ColumnType>,
+    seen: bool,
+}
+
+impl Default for TypeInference {
+    fn default() -> Self {
+        Self {
+            candidates: INFER_ORDER.to_vec(),
+            seen: false,
+        }
+    }
+}
+
+impl TypeInference {
+    pub fn update(&mut self, cell: &str) {
+        if cell.is_empty() {
+            return;
+        }
+        self.seen = true;
+        if !self.candidates.is_empty() {
+            self.candidates.retain(|t| t.matches(cell));
+        }
+    }
+
+    /// a column without any non-empty cell is a string column
+    pub fn column_type(&self) -> ColumnType {
+        match self.candidates.first() {
+            Some(t) if self.seen => *t,
+            _ => ColumnType::String,
+        }
+    }
+}
+
+/// Load an explicit column type schema, a yaml or json map of column name to type
+pub fn load_column_schema(path: &str) -> anyhow::Result<HashMap<String, ColumnType>> {
+    let content = fs::read_to_string(path)?;
+    let schema = serde_yaml::from_str(&content)?;
+    Ok(schema)
+}
+
+impl From<ColumnType> for String {
+    fn from(t: ColumnType) -> Self {
+        match t {
+            ColumnType::Boolean => "boolean".into(),
+            ColumnType::Integer => "integer".into(),
+            ColumnType::Float => "float".into(),
+            ColumnType::Date => "date".into(),
+            ColumnType::String => "string".into(),
+        }
+    }
+}
+
+impl FromStr for ColumnType {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "boolean" | "bool" => Ok(ColumnType::Boolean),
+            "integer" | "int" => Ok(ColumnType::Integer),
+            "float" | "number" => Ok(ColumnType::Float),
+            "date" => Ok(ColumnType::Date),
+            "string" => Ok(ColumnType::String),
+            _ => Err(anyhow::anyhow!("Invalid column type")),
+        }
+    }
+}
+
+impl Display for ColumnType {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", String::from(*self))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_infer_column_types() {
+        let sample = vec![
+            StringRecord::from(vec!["1", "1.5", "true", "2019-04-18", "007", "x"]),
+            StringRecord::from(vec!["-20", "3", "FALSE", "", "012", "1"]),
+            StringRecord::from(vec!["", "", "", "2020-01-01T10:00:00Z", "", ""]),
+        ];
+        assert_eq!(
+            infer_column_types(7, &sample),
+            vec![
+                ColumnType::Integer,
+                ColumnType::Float,
+                ColumnType::Boolean,
+                ColumnType::Date,
+                ColumnType::String,
+                ColumnType::String,
+                ColumnType::String,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_convert() {
+        assert_eq!(ColumnType::Integer.convert("42"), json!(42));
+        assert_eq!(ColumnType::Integer.convert("4x"), json!("4x"));
+        assert_eq!(ColumnType::Float.convert("2.5"), json!(2.5));
+        assert_eq!(ColumnType::Float.convert("NaN"), json!("NaN"));
+        assert_eq!(ColumnType::Boolean.convert("True"), json!(true));
+        assert_eq!(ColumnType::Date.convert("2019-04-18"), json!("2019-04-18"));
+        assert_eq!(ColumnType::String.convert(""), Value::Null);
+    }
+}
@@ -0,0 +1,349 @@
+use std::{cmp::Ordering, collections::HashMap, fs};
+
+use csv::StringRecord;
+use serde_json::{Map, Value};
+
+use crate::{
+    cli::{CsvJoinOpts, JoinType},
+    writer_for_output,
+};
+
+use super::{
+    csv_filter::column_index,
+    csv_reader::CsvSource,
+    csv_sort::{compare_keys, external_sort, key_order, SortedRecords},
+    csv_writer::RecordWriter,
+};
+
+/// Output columns of a join: every left column, then the right columns that
+/// are not part of the key
+struct JoinLayout {
+    left_key: Vec<usize>,
+    right_key: Vec<usize>,
+    left_width: usize,
+    right_columns: Vec<usize>,
+    names: Vec<String>,
+}
+
+pub fn process_csv_join(opts: &CsvJoinOpts) -> anyhow::Result<()> {
+    let mut left = CsvSource::open(&opts.left, &opts.reader)?;
+    let mut right = CsvSource::open(&opts.right, &opts.reader)?;
+    let layout = JoinLayout::new(&opts.on, &left.headers, &right.headers)?;
+    let (keep_left, keep_right) = match opts.join_type {
+        JoinType::Inner => (false, false),
+        JoinType::Left => (true, false),
+        JoinType::Right => (false, true),
+        JoinType::Full => (true, true),
+    };
+
+    let writer = writer_for_output(opts.output.as_deref())?;
+    let mut writer = RecordWriter::new(writer, opts.format, layout.names.clone());
+    let mut emit = |l: Option<&StringRecord>, r: Option<&StringRecord>| {
+        let row = layout
+            .names
+            .iter()
+            .cloned()
+            .zip(layout.row(l, r).into_iter().map(Value::String))
+            .collect::<Map<_, _>>();
+        writer.write(&Value::Object(row))
+    };
+
+    let max_bytes = opts.memory_limit * 1024 * 1024;
+    let (left_size, right_size) = (input_size(&opts.left), input_size(&opts.right));
+    if right_size <= max_bytes as u64 && right_size <= left_size {
+        hash_join(
+            (&mut left, &layout.left_key, keep_left),
+            (&mut right, &layout.right_key, keep_right),
+            &mut emit,
+        )?;
+    } else if left_size <= max_bytes as u64 {
+        // the left side is the smaller one, build on it and stream the right
+        hash_join(
+            (&mut right, &layout.right_key, keep_right),
+            (&mut left, &layout.left_key, keep_left),
+            &mut |r, l| emit(l, r),
+        )?;
+    } else {
+        let left = external_sort(&mut left, max_bytes, key_order(layout.left_key.clone()))?;
+        let right = external_sort(&mut right, max_bytes, key_order(layout.right_key.clone()))?;
+        merge_join(
+            (left, &layout.left_key, keep_left),
+            (right, &layout.right_key, keep_right),
+            &mut emit,
+        )?;
+    }
+    writer.finish()?;
+    Ok(())
+}
+
+/// stdin has an unknown size and is never used as the in-memory side
+fn input_size(input: &str) -> u64 {
+    if input == "-" {
+        return u64::MAX;
+    }
+    fs::metadata(input).map_or(u64::MAX, |m| m.len())
+}
+
+impl JoinLayout {
+    /// `on` entries are a column name shared by both files or `left=right`
+    fn new(on: &[String], left: &[String], right: &[String]) -> anyhow::Result<Self> {
+        let mut left_key = Vec::new();
+        let mut right_key = Vec::new();
+        for column in on {
+            let (l, r) = column.split_once('=').unwrap_or((column, column));
+            left_key.push(column_index(left, l)?);
+            right_key.push(column_index(right, r)?);
+        }
+        let right_columns = (0..right.len())
+            .filter(|i| !right_key.contains(i))
+            .collect::<Vec<_>>();
+        let mut names = left.to_vec();
+        for &i in &right_columns {
+            // a right column clashing with a left one gets a suffix
+            let mut name = right[i].clone();
+            while names.contains(&name) {
+                name.push_str("_right");
+            }
+            names.push(name);
+        }
+        Ok(Self {
+            left_key,
+            right_key,
+            left_width: left.len(),
+            right_columns,
+            names,
+        })
+    }
+
+    /// a missing left row still shows the key, taken from the right row
+    fn row(&self, left: Option<&StringRecord>, right: Option<&StringRecord>) -> Vec<String> {
+        let mut row = (0..self.left_width)
+            .map(|i| {
+                let cell = match (left, right) {
+                    (Some(l), _) => l.get(i),
+                    (None, Some(r)) => self
+                        .left_key
+                        .iter()
+                        .position(|&k| k == i)
+                        .and_then(|p| r.get(self.right_key[p])),
+                    (None, None) => None,
+                };
+                cell.unwrap_or_default().to_string()
+            })
+            .collect::<Vec<_>>();
+        row.extend(
+            self.right_columns
+                .iter()
+                .map(|&i| right.and_then(|r| r.get(i)).unwrap_or_default().to_string()),
+        );
+        row
+    }
+}
+
+fn key_of(record: &StringRecord, key: &[usize]) -> Vec<String> {
+    key.iter()
+        .map(|&i| record.get(i).unwrap_or_default().to_string())
+        .collect()
+}
+
+type JoinSide<'a, R> = (R, &'a [usize], bool);
+type Emit<'a> = dyn FnMut(Option<&StringRecord>, Option<&StringRecord>) -> anyhow::Result<()> + 'a;
+
+/// Load the build side into a hash table and stream the probe side through it.
+/// Rows are emitted as (probe, build) pairs.
+fn hash_join(
+    (probe, probe_key, keep_probe): JoinSide<&mut CsvSource>,
+    (build, build_key, keep_build): JoinSide<&mut CsvSource>,
+    emit: &mut Emit,
+) -> anyhow::Result<()> {
+    // build rows in input order, whether they matched, and their positions by key
+    let mut rows = Vec::new();
+    let mut table: HashMap<Vec<String>, Vec<usize>> = HashMap::new();
+    let mut record = StringRecord::new();
+    while build.read_record(&mut record)? {
+        table
+            .entry(key_of(&record, build_key))
+            .or_default()
+            .push(rows.len());
+        rows.push((record.clone(), false));
+    }
+
+    while probe.read_record(&mut record)? {
+        match table.get(&key_of(&record, probe_key)) {
+            Some(matches) => {
+                for &i in matches {
+                    rows[i].1 = true;
+                    emit(Some(&record), Some(&rows[i].0))?;
+                }
+            }
+            None if keep_probe => emit(Some(&record), None)?,
+            None => {}
+        }
+    }
+
+    if keep_build {
+        for (build_record, _) in rows.iter().filter(|(_, matched)| !matched) {
+            emit(None, Some(build_record))?;
+        }
+    }
+    Ok(())
+}
+
+/// Join two key sorted sides, equal keys are joined group by group so only one
+/// key's rows are held in memory
+fn merge_join(
+    (mut left, left_key, keep_left): JoinSide<SortedRecords>,
+    (mut right, right_key, keep_right): JoinSide<SortedRecords>,
+    emit: &mut Emit,
+) -> anyhow::Result<()> {
+    let mut left_head = left.next().transpose()?;
+    let mut right_head = right.next().transpose()?;
+    loop {
+        let ordering = match (&left_head, &right_head) {
+            (None, None) => return Ok(()),
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (Some(l), Some(r)) => compare_keys(l, left_key, r, right_key),
+        };
+        match ordering {
+            Ordering::Less => {
+                if keep_left {
+                    emit(left_head.as_ref(), None)?;
+                }
+                left_head = left.next().transpose()?;
+            }
+            Ordering::Greater => {
+                if keep_right {
+                    emit(None, right_head.as_ref())?;
+                }
+                right_head = right.next().transpose()?;
+            }
+            Ordering::Equal => {
+                let (lefts, next_left) = take_group(&mut left, left_head.take(), left_key)?;
+                let (rights, next_right) = take_group(&mut right, right_head.take(), right_key)?;
+                for l in &lefts {
+                    for r in &rights {
+                        emit(Some(l), Some(r))?;
+                    }
+                }
+                left_head = next_left;
+                right_head = next_right;
+            }
+        }
+    }
+}
+
+/// the run of records sharing the key of `head`, and the first record after it
+fn take_group(
+    records: &mut SortedRecords,
+    head: Option<StringRecord>,
+    key: &[usize],
+) -> anyhow::Result<(Vec<StringRecord>, Option<StringRecord>)> {
+    let mut group = head.into_iter().collect::<Vec<_>>();
+    for record in records.by_ref() {
+        let record = record?;
+        if compare_keys(&record, key, &group[0], key).is_ne() {
+            return Ok((group, Some(record)));
+        }
+        group.push(record);
+    }
+    Ok((group, None))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::CsvReaderOpts;
+    use clap::Parser;
+
+    fn open(content: &str) -> anyhow::Result<(tempfile::NamedTempFile, CsvSource)> {
+        let file = tempfile::NamedTempFile::new()?;
+        fs::write(file.path(), content)?;
+        let opts = CsvReaderOpts::parse_from(["join"]);
+        let source = CsvSource::open(file.path().to_str().unwrap(), &opts)?;
+        Ok((file, source))
+    }
+
+    /// run both join strategies, rows are returned sorted for comparison
+    fn join(keep_left: bool, keep_right: bool) -> anyhow::Result<[Vec<Vec<String>>; 2]> {
+        let left = "id,name\n1,Buffon\n2,Perin\n2,Pinsoglio\n4,Chiellini\n";
+        let right = "team_id,id,club\n9,2,Genoa\n8,1,Juventus\n7,3,Torino\n";
+        let on = ["id".to_string()];
+        let mut results = [Vec::new(), Vec::new()];
+
+        let (_l, mut l) = open(left)?;
+        let (_r, mut r) = open(right)?;
+        let layout = JoinLayout::new(&on, &l.headers, &r.headers)?;
+        let mut rows = Vec::new();
+        hash_join(
+            (&mut l, &layout.left_key, keep_left),
+            (&mut r, &layout.right_key, keep_right),
+            &mut |a, b| {
+                rows.push(layout.row(a, b));
+                Ok(())
+            },
+        )?;
+        results[0] = rows;
+
+        let (_l, mut l) = open(left)?;
+        let (_r, mut r) = open(right)?;
+        let mut rows = Vec::new();
+        merge_join(
+            (
+                external_sort(&mut l, 8, key_order(layout.left_key.clone()))?,
+                &layout.left_key,
+                keep_left,
+            ),
+            (
+                external_sort(&mut r, 8, key_order(layout.right_key.clone()))?,
+                &layout.right_key,
+                keep_right,
+            ),
+            &mut |a, b| {
+                rows.push(layout.row(a, b));
+                Ok(())
+            },
+        )?;
+        results[1] = rows;
+        for rows in results.iter_mut() {
+            rows.sort();
+        }
+        Ok(results)
+    }
+
+    #[test]
+    fn test_join_layout() -> anyhow::Result<()> {
+        let left = ["id", "club"].map(String::from);
+        let right = ["club_id", "club", "city"].map(String::from);
+        let layout = JoinLayout::new(&["id=club_id".to_string()], &left, &right)?;
+        assert_eq!(layout.names, ["id", "club", "club_right", "city"]);
+        let r = StringRecord::from(vec!["7", "Torino", "Torino"]);
+        assert_eq!(layout.row(None, Some(&r)), ["7", "", "Torino", "Torino"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_join_types() -> anyhow::Result<()> {
+        let row = |cells: [&str; 4]| cells.map(String::from).to_vec();
+        let [hash, merge] = join(false, false)?;
+        let inner = vec![
+            row(["1", "Buffon", "8", "Juventus"]),
+            row(["2", "Perin", "9", "Genoa"]),
+            row(["2", "Pinsoglio", "9", "Genoa"]),
+        ];
+        assert_eq!(hash, inner);
+        assert_eq!(merge, inner);
+
+        let [hash, merge] = join(true, true)?;
+        let mut full = inner.clone();
+        full.push(row(["3", "", "7", "Torino"]));
+        full.push(row(["4", "Chiellini", "", ""]));
+        assert_eq!(hash, full);
+        assert_eq!(merge, full);
+
+        let [hash, merge] = join(false, true)?;
+        assert_eq!(hash.len(), 4);
+        assert_eq!(merge, hash);
+        Ok(())
+    }
+}
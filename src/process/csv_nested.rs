@@ -0,0 +1,240 @@
+use serde_json::{Map, Value};
+
+/// One step of a dotted column name, `address.city` or `tags[0]`
+#[derive(Debug, Clone, PartialEq)]
+pub enum PathSegment {
+    Key(String),
+    Index(usize),
+}
+
+/// Builds nested records from flat cells, one path per output column
+#[derive(Debug)]
+pub struct NestedLayout {
+    paths: Vec<Vec<PathSegment>>,
+}
+
+impl NestedLayout {
+    /// Fails when one column would overwrite another, such as `a` next to `a.b`
+    /// or `a[0]` next to `a.x`, or when an index is not below the number of
+    /// columns
+    pub fn new(names: &[String]) -> anyhow::Result<Self> {
+        let layout = Self {
+            paths: names
+                .iter()
+                .map(|name| parse_path(name, names.len()))
+                .collect::<anyhow::Result<_>>()?,
+        };
+        // a dry run with non-null leaves finds every clash up front
+        layout.build(names.iter().map(|_| Value::Bool(true)))?;
+        Ok(layout)
+    }
+
+    pub fn build(&self, cells: impl Iterator<Item = Value>) -> anyhow::Result<Value> {
+        let mut record = Value::Object(Map::new());
+        for (path, cell) in self.paths.iter().zip(cells) {
+            if !insert(&mut record, path, cell) {
+                anyhow::bail!(
+                    "Column {:?} clashes with another nested column",
+                    path_text(path)
+                );
+            }
+        }
+        Ok(record)
+    }
+}
+
+/// Split a column name on `.` and `[n]`, a part whose brackets do not hold an
+/// index is kept as a plain key. Indices have to be below `columns`, a header
+/// cannot fill a longer array.
+pub fn parse_path(name: &str, columns: usize) -> anyhow::Result<Vec<PathSegment>> {
+    let mut path = Vec::new();
+    for part in name.split('.') {
+        match part.find('[') {
+            Some(start) => match parse_indices(name, &part[start..], columns)? {
+                Some(indices) => {
+                    if start > 0 {
+                        path.push(PathSegment::Key(part[..start].to_string()));
+                    }
+                    path.extend(indices.into_iter().map(PathSegment::Index));
+                }
+                None => path.push(PathSegment::Key(part.to_string())),
+            },
+            None => path.push(PathSegment::Key(part.to_string())),
+        }
+    }
+    Ok(path)
+}
+
+/// `[0][12]` as indices, None when the brackets hold something else
+fn parse_indices(name: &str, mut s: &str, columns: usize) -> anyhow::Result<Option<Vec<usize>>> {
+    let mut indices = Vec::new();
+    while !s.is_empty() {
+        let Some(end) = s.find(']') else {
+            return Ok(None);
+        };
+        let Some(digits) = s.strip_prefix('[').map(|inner| &inner[..end - 1]) else {
+            return Ok(None);
+        };
+        if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+            return Ok(None);
+        }
+        match digits.parse::<usize>() {
+            Ok(i) if i < columns => indices.push(i),
+            _ => anyhow::bail!(
+                "Column {:?} has index {}, indices have to be below the {} columns",
+                name,
+                digits,
+                columns
+            ),
+        }
+        s = &s[end + 1..];
+    }
+    Ok(Some(indices))
+}
+
+fn path_text(path: &[PathSegment]) -> String {
+    let mut text = String::new();
+    for segment in path {
+        match segment {
+            PathSegment::Key(key) if text.is_empty() => text.push_str(key),
+            PathSegment::Key(key) => {
+                text.push('.');
+                text.push_str(key);
+            }
+            PathSegment::Index(i) => text.push_str(&format!("[{}]", i)),
+        }
+    }
+    text
+}
+
+/// put `value` at `path`, creating objects and arrays on the way. Returns false
+/// when the path runs into a value set before.
+fn insert(target: &mut Value, path: &[PathSegment], value: Value) -> bool {
+    let Some((first, rest)) = path.split_first() else {
+        if target.is_null() {
+            *target = value;
+            return true;
+        }
+        return false;
+    };
+    match first {
+        PathSegment::Key(key) => {
+            if target.is_null() {
+                *target = Value::Object(Map::new());
+            }
+            let Value::Object(map) = target else {
+                return false;
+            };
+            insert(map.entry(key.clone()).or_insert(Value::Null), rest, value)
+        }
+        PathSegment::Index(i) => {
+            if target.is_null() {
+                *target = Value::Array(Vec::new());
+            }
+            let Value::Array(values) = target else {
+                return false;
+            };
+            let Some(len) = i.checked_add(1) else {
+                return false;
+            };
+            if values.len() < len {
+                values.resize(len, Value::Null);
+            }
+            insert(&mut values[*i], rest, value)
+        }
+    }
+}
+
+/// Flatten nested objects and arrays into dotted keys, the reverse of
+/// [`NestedLayout::build`]. Empty objects and arrays are kept as values.
+pub fn flatten(record: &Map<String, Value>) -> Map<String, Value> {
+    let mut flat = Map::new();
+    for (key, value) in record {
+        flatten_into(key.clone(), value, &mut flat);
+    }
+    flat
+}
+
+fn flatten_into(prefix: String, value: &Value, flat: &mut Map<String, Value>) {
+    match value {
+        Value::Object(map) if !map.is_empty() => {
+            for (key, value) in map {
+                flatten_into(format!("{}.{}", prefix, key), value, flat);
+            }
+        }
+        Value::Array(values) if !values.is_empty() => {
+            for (i, value) in values.iter().enumerate() {
+                flatten_into(format!("{}[{}]", prefix, i), value, flat);
+            }
+        }
+        _ => {
+            flat.insert(prefix, value.clone());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_parse_path() -> anyhow::Result<()> {
+        use PathSegment::*;
+        assert_eq!(
+            parse_path("a.b[1][0].c", 5)?,
+            [
+                Key("a".into()),
+                Key("b".into()),
+                Index(1),
+                Index(0),
+                Key("c".into())
+            ]
+        );
+        assert_eq!(parse_path("price[usd]", 5)?, [Key("price[usd]".into())]);
+        assert_eq!(parse_path("Kit Number", 5)?, [Key("Kit Number".into())]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_index_beyond_columns() {
+        for name in ["tags[99999999999]", "tags[18446744073709551615]", "tags[2]"] {
+            let names = ["id".to_string(), name.to_string()];
+            let err = NestedLayout::new(&names).unwrap_err();
+            assert!(err.to_string().contains("below the 2 columns"), "{}", err);
+        }
+        assert!(NestedLayout::new(&["tags[1]", "tags[0]"].map(String::from)).is_ok());
+    }
+
+    #[test]
+    fn test_round_trip() -> anyhow::Result<()> {
+        let names = ["id", "address.city", "address.zip", "tags[1]", "tags[0]"].map(String::from);
+        let layout = NestedLayout::new(&names)?;
+        let cells = [
+            json!(1),
+            json!("Torino"),
+            json!(10100),
+            json!("b"),
+            json!(null),
+        ];
+        let record = layout.build(cells.clone().into_iter())?;
+        assert_eq!(
+            record,
+            json!({"id": 1, "address": {"city": "Torino", "zip": 10100}, "tags": [null, "b"]})
+        );
+        let flat = flatten(record.as_object().unwrap());
+        assert_eq!(
+            flat.keys().collect::<Vec<_>>(),
+            ["id", "address.city", "address.zip", "tags[0]", "tags[1]"]
+        );
+        assert_eq!(flat["tags[1]"], json!("b"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_clashing_columns() {
+        for names in [["a", "a.b"], ["a[0]", "a.x"], ["a.b", "a.b"]] {
+            assert!(NestedLayout::new(&names.map(String::from)).is_err());
+        }
+    }
+}
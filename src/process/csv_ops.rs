@@ -0,0 +1,399 @@
+use std::{
+    cmp::Ordering,
+    collections::{HashMap, HashSet},
+    fs::OpenOptions,
+    io::{BufWriter, Write},
+};
+
+use csv::StringRecord;
+
+use crate::cli::{CsvCatOpts, CsvDedupOpts, CsvSortOpts, CsvSplitOpts, SortKey};
+
+use super::{
+    csv_filter::column_index,
+    csv_reader::CsvSource,
+    csv_sort::{external_sort, RecordOrder},
+    csv_writer::{csv_output, csv_writer},
+};
+
+/// files `split --by` keeps open at once, the least recently written one is
+/// closed to make room and reopened for appending when its value comes back
+const MAX_OPEN_PARTS: usize = 64;
+
+pub fn process_csv_sort(opts: &CsvSortOpts) -> anyhow::Result<()> {
+    let mut reader = CsvSource::open(&opts.input, &opts.reader)?;
+    let order = sort_order(&opts.key, &reader.headers)?;
+    let mut writer = csv_output(opts.output.as_deref(), &opts.reader)?;
+    if opts.reader.header {
+        writer.write_record(&reader.headers)?;
+    }
+    for record in external_sort(&mut reader, opts.memory_limit * 1024 * 1024, order)? {
+        writer.write_record(&record?)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Compare on each key in turn, the first one that differs decides
+fn sort_order(keys: &[SortKey], headers: &[String]) -> anyhow::Result<RecordOrder> {
+    let keys = keys
+        .iter()
+        .map(|key| {
+            Ok((
+                column_index(headers, &key.column)?,
+                key.numeric,
+                key.reverse,
+            ))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    Ok(Box::new(move |a, b| {
+        for &(i, numeric, reverse) in &keys {
+            let (x, y) = (a.get(i).unwrap_or_default(), b.get(i).unwrap_or_default());
+            let ordering = if numeric {
+                compare_numeric(x, y)
+            } else {
+                x.cmp(y)
+            };
+            let ordering = if reverse {
+                ordering.reverse()
+            } else {
+                ordering
+            };
+            if ordering.is_ne() {
+                return ordering;
+            }
+        }
+        Ordering::Equal
+    }))
+}
+
+/// numbers before anything else, so the order stays total on mixed columns
+fn compare_numeric(a: &str, b: &str) -> Ordering {
+    match (a.trim().parse::<f64>(), b.trim().parse::<f64>()) {
+        (Ok(x), Ok(y)) => x.total_cmp(&y),
+        (Ok(_), Err(_)) => Ordering::Less,
+        (Err(_), Ok(_)) => Ordering::Greater,
+        (Err(_), Err(_)) => a.cmp(b),
+    }
+}
+
+pub fn process_csv_dedup(opts: &CsvDedupOpts) -> anyhow::Result<()> {
+    let mut reader = CsvSource::open(&opts.input, &opts.reader)?;
+    let key = opts
+        .key
+        .iter()
+        .map(|name| column_index(&reader.headers, name))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let mut writer = csv_output(opts.output.as_deref(), &opts.reader)?;
+    if opts.reader.header {
+        writer.write_record(&reader.headers)?;
+    }
+
+    let mut seen = HashSet::new();
+    let mut record = StringRecord::new();
+    while reader.read_record(&mut record)? {
+        let cells = if key.is_empty() {
+            record.iter().map(String::from).collect::<Vec<_>>()
+        } else {
+            key.iter()
+                .map(|&i| record.get(i).unwrap_or_default().to_string())
+                .collect()
+        };
+        if seen.insert(cells) {
+            writer.write_record(&record)?;
+        }
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+pub fn process_csv_split(opts: &CsvSplitOpts) -> anyhow::Result<()> {
+    let mut reader = CsvSource::open(&opts.input, &opts.reader)?;
+    let by = opts
+        .by
+        .as_deref()
+        .map(|name| column_index(&reader.headers, name))
+        .transpose()?;
+
+    // open parts with the row they were last written at
+    let mut parts: HashMap<String, (csv::Writer<Box<dyn Write>>, usize)> = HashMap::new();
+    let mut created = HashSet::new();
+    // part of every `--by` value, values sharing a file name get a suffix
+    let mut value_parts: HashMap<String, String> = HashMap::new();
+    let mut taken = HashSet::new();
+    let mut rows = 0;
+    let mut record = StringRecord::new();
+    while reader.read_record(&mut record)? {
+        let part = match (by, opts.rows) {
+            (Some(i), _) => {
+                let value = record.get(i).unwrap_or_default();
+                match value_parts.get(value) {
+                    Some(part) => part.clone(),
+                    None => {
+                        let part = unique_part(file_name_part(value), &taken);
+                        taken.insert(part.clone());
+                        value_parts.insert(value.to_string(), part.clone());
+                        part
+                    }
+                }
+            }
+            (None, Some(n)) => (rows / n + 1).to_string(),
+            (None, None) => anyhow::bail!("Either --rows or --by is required"),
+        };
+        rows += 1;
+        if !parts.contains_key(&part) {
+            if parts.len() == MAX_OPEN_PARTS {
+                let oldest = parts
+                    .iter()
+                    .min_by_key(|(_, (_, used))| *used)
+                    .map(|(part, _)| part.clone());
+                if let Some((mut writer, _)) = oldest.and_then(|part| parts.remove(&part)) {
+                    writer.flush()?;
+                }
+            }
+            let path = opts
+                .output_dir
+                .join(format!("{}_{}.csv", opts.prefix, part));
+            let writer = if created.insert(part.clone()) {
+                let mut writer = csv_output(Some(&path.to_string_lossy()), &opts.reader)?;
+                if opts.reader.header {
+                    writer.write_record(&reader.headers)?;
+                }
+                writer
+            } else {
+                let file = OpenOptions::new().append(true).open(&path)?;
+                csv_writer(Box::new(BufWriter::new(file)), &opts.reader)?
+            };
+            parts.insert(part.clone(), (writer, rows));
+        }
+        if let Some((writer, used)) = parts.get_mut(&part) {
+            writer.write_record(&record)?;
+            *used = rows;
+        }
+        // parts by row count are finished once the next one starts
+        if by.is_none() && opts.rows.is_some_and(|n| rows % n == 0) {
+            for (_, (mut writer, _)) in parts.drain() {
+                writer.flush()?;
+            }
+        }
+    }
+    for (writer, _) in parts.values_mut() {
+        writer.flush()?;
+    }
+    Ok(())
+}
+
+/// `part`, or `part_2`, `part_3`… when another value already has that file
+fn unique_part(part: String, taken: &HashSet<String>) -> String {
+    if !taken.contains(&part) {
+        return part;
+    }
+    (2..)
+        .map(|n| format!("{}_{}", part, n))
+        .find(|name| !taken.contains(name))
+        .unwrap_or(part)
+}
+
+/// keep a column value usable as part of a file name
+fn file_name_part(value: &str) -> String {
+    let part = value
+        .chars()
+        .map(|c| {
+            if c.is_alphanumeric() || matches!(c, '-' | '_' | '.') {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect::<String>();
+    if part.is_empty() {
+        "empty".to_string()
+    } else {
+        part
+    }
+}
+
+pub fn process_csv_cat(opts: &CsvCatOpts) -> anyhow::Result<()> {
+    let mut sources = opts
+        .inputs
+        .iter()
+        .map(|input| CsvSource::open(input, &opts.reader))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let headers = union_headers(sources.iter().map(|s| s.headers.as_slice()));
+
+    let mut writer = csv_output(opts.output.as_deref(), &opts.reader)?;
+    if opts.reader.header {
+        writer.write_record(&headers)?;
+    }
+    let mut record = StringRecord::new();
+    for source in sources.iter_mut() {
+        // output position of every column of this file, the n-th column of a
+        // name goes to the n-th output column of that name
+        let mut seen = HashMap::new();
+        let positions = source
+            .headers
+            .iter()
+            .map(|h| {
+                let n = seen.entry(h).or_insert(0);
+                *n += 1;
+                headers
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, name)| *name == h)
+                    .nth(*n - 1)
+                    .map(|(position, _)| position)
+                    .unwrap_or_default()
+            })
+            .collect::<Vec<_>>();
+        let mut row = vec![String::new(); headers.len()];
+        while source.read_record(&mut record)? {
+            row.iter_mut().for_each(String::clear);
+            for (cell, &position) in record.iter().zip(&positions) {
+                row[position].push_str(cell);
+            }
+            writer.write_record(&row)?;
+        }
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// every column name in first seen order, a name repeated within a file is
+/// kept as often as the file repeating it most
+fn union_headers<'a>(files: impl Iterator<Item = &'a [String]>) -> Vec<String> {
+    let mut headers: Vec<String> = Vec::new();
+    for file in files {
+        let mut seen = HashMap::new();
+        for name in file {
+            let n = seen.entry(name).or_insert(0);
+            *n += 1;
+            if headers.iter().filter(|h| *h == name).count() < *n {
+                headers.push(name.clone());
+            }
+        }
+    }
+    headers
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    #[test]
+    fn test_sort_order() -> anyhow::Result<()> {
+        let headers = ["Name", "Nationality", "Kit Number"].map(String::from);
+        let opts = CsvSortOpts::parse_from(["sort", "-k", "Nationality,Kit Number:nr"]);
+        let order = sort_order(&opts.key, &headers)?;
+        let mut rows = [
+            ["Perin", "Italy", "37"],
+            ["Szczesny", "Poland", "1"],
+            ["Buffon", "Italy", "77"],
+            ["Chiellini", "Italy", "3"],
+            ["Pjaca", "Croatia", "x"],
+        ]
+        .map(|r| StringRecord::from(r.to_vec()));
+        rows.sort_by(|a, b| order(a, b));
+        let names = rows.iter().map(|r| r[0].to_string()).collect::<Vec<_>>();
+        assert_eq!(names, ["Pjaca", "Buffon", "Perin", "Chiellini", "Szczesny"]);
+        assert_eq!(compare_numeric("10", "9"), Ordering::Greater);
+        assert_eq!(compare_numeric("x", "9"), Ordering::Greater);
+        Ok(())
+    }
+
+    #[test]
+    fn test_union_headers() {
+        let a = ["id", "name"].map(String::from);
+        let b = ["name", "age", "id"].map(String::from);
+        assert_eq!(
+            union_headers([&a[..], &b[..]].into_iter()),
+            ["id", "name", "age"]
+        );
+        let c = ["x", "id", "x"].map(String::from);
+        assert_eq!(
+            union_headers([&a[..], &c[..], &b[..]].into_iter()),
+            ["id", "name", "x", "x", "age"]
+        );
+        assert_eq!(
+            file_name_part("Bosnia Herzegovina/1"),
+            "Bosnia_Herzegovina_1"
+        );
+    }
+
+    #[test]
+    fn test_cat_duplicate_headers() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let (a, b, out) = (
+            dir.path().join("a.csv"),
+            dir.path().join("b.csv"),
+            dir.path().join("out.csv"),
+        );
+        std::fs::write(&a, "x,id,x\na,1,b\n")?;
+        std::fs::write(&b, "id,x\n2,c\n")?;
+        let path = |p: &std::path::Path| p.to_str().unwrap().to_string();
+        process_csv_cat(&CsvCatOpts::parse_from([
+            "cat".to_string(),
+            path(&a),
+            path(&b),
+            "-o".to_string(),
+            path(&out),
+        ]))?;
+        assert_eq!(std::fs::read_to_string(&out)?, "x,id,x\na,1,b\nc,2,\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_split_by_many_values() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let input = dir.path().join("in.csv");
+        let mut csv = String::from("id,group\n");
+        for i in 0..2 * (MAX_OPEN_PARTS + 36) {
+            csv.push_str(&format!("{},g{}\n", i, i % (MAX_OPEN_PARTS + 36)));
+        }
+        std::fs::write(&input, csv)?;
+        process_csv_split(&CsvSplitOpts::parse_from([
+            "split",
+            "-i",
+            input.to_str().unwrap(),
+            "--by",
+            "group",
+            "--output-dir",
+            dir.path().to_str().unwrap(),
+        ]))?;
+        let part = std::fs::read_to_string(dir.path().join("part_g7.csv"))?;
+        assert_eq!(
+            part,
+            format!("id,group\n7,g7\n{},g7\n", MAX_OPEN_PARTS + 43)
+        );
+
+        assert!(CsvSplitOpts::try_parse_from(["split", "--rows", "0"]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_split_colliding_values() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let input = dir.path().join("in.csv");
+        std::fs::write(
+            &input,
+            "id,group\n1,a/b\n2,a_b\n3,\n4,empty\n5,a/b\n6,a?b\n",
+        )?;
+        process_csv_split(&CsvSplitOpts::parse_from([
+            "split",
+            "-i",
+            input.to_str().unwrap(),
+            "--by",
+            "group",
+            "--output-dir",
+            dir.path().to_str().unwrap(),
+        ]))?;
+        let part =
+            |name: &str| std::fs::read_to_string(dir.path().join(format!("part_{}.csv", name)));
+        assert_eq!(part("a_b")?, "id,group\n1,a/b\n5,a/b\n");
+        assert_eq!(part("a_b_2")?, "id,group\n2,a_b\n");
+        assert_eq!(part("a_b_3")?, "id,group\n6,a?b\n");
+        assert_eq!(part("empty")?, "id,group\n3,\n");
+        assert_eq!(part("empty_2")?, "id,group\n4,empty\n");
+        Ok(())
+    }
+}
@@ -0,0 +1,542 @@
+use std::{cmp::Ordering, collections::HashMap};
+
+use csv::StringRecord;
+use serde_json::Value;
+
+use crate::{cli::CsvQueryOpts, writer_for_output};
+
+use super::{
+    csv_filter::{column_index, compare_cells, tokenize, Expr, ExprParser, Token},
+    csv_infer::infer_column_types,
+    csv_reader::CsvSource,
+    csv_writer::RecordWriter,
+};
+
+/// A parsed `SELECT` over the columns of one CSV file
+#[derive(Debug)]
+pub struct Query {
+    /// selected items followed by the columns only used by ORDER BY
+    items: Vec<SelectItem>,
+    visible: usize,
+    filter: Option<Expr>,
+    group_by: Vec<usize>,
+    /// output column and descending flag
+    order_by: Vec<(usize, bool)>,
+    limit: Option<usize>,
+}
+
+#[derive(Debug)]
+struct SelectItem {
+    /// alias, or the text of the item
+    name: String,
+    /// text of the item, `count(*)`, so ORDER BY can use it despite an alias
+    text: String,
+    kind: ItemKind,
+}
+
+#[derive(Debug)]
+enum ItemKind {
+    Column(usize),
+    /// `count(*)` has no column
+    Aggregate(Aggregate, Option<usize>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Aggregate {
+    Count,
+    Sum,
+    Avg,
+    Min,
+    Max,
+}
+
+pub fn process_csv_query(opts: &CsvQueryOpts) -> anyhow::Result<()> {
+    let mut reader = CsvSource::open(&opts.input, &opts.reader)?;
+    let query = Query::parse(&opts.query, &reader.headers)?;
+    let (names, rows) = query.run(&mut reader)?;
+
+    let types = infer_column_types(names.len(), &rows);
+    let writer = writer_for_output(opts.output.as_deref())?;
+    let mut writer = RecordWriter::new(writer, opts.format, names.clone());
+    for row in &rows {
+        let value = names
+            .iter()
+            .zip(&types)
+            .zip(row.iter())
+            .map(|((name, t), cell)| (name.clone(), t.convert(cell)))
+            .collect::<serde_json::Map<_, _>>();
+        writer.write(&Value::Object(value))?;
+    }
+    writer.finish()?;
+    Ok(())
+}
+
+impl Query {
+    /// Parse `SELECT items FROM table [WHERE expr] [GROUP BY cols]
+    /// [ORDER BY col [ASC|DESC], ...] [LIMIT n]`, the table name is not checked
+    pub fn parse(sql: &str, headers: &[String]) -> anyhow::Result<Self> {
+        let tokens = tokenize(sql)?;
+        let mut parser = ExprParser::new(&tokens, headers);
+        if !parser.keyword("select") {
+            anyhow::bail!("Query should start with SELECT");
+        }
+
+        let mut items = Vec::new();
+        loop {
+            items.extend(parse_select_item(&mut parser, headers)?);
+            if parser.peek() != Some(&Token::Comma) {
+                break;
+            }
+            parser.next();
+        }
+
+        if !parser.keyword("from") || !matches!(parser.next(), Some(Token::Ident(_))) {
+            anyhow::bail!("Expected FROM <table>");
+        }
+        let filter = if parser.keyword("where") {
+            Some(parser.parse_expr()?)
+        } else {
+            None
+        };
+
+        let mut group_by = Vec::new();
+        if parser.keyword("group") {
+            if !parser.keyword("by") {
+                anyhow::bail!("Expected GROUP BY");
+            }
+            for name in parse_names(&mut parser)? {
+                group_by.push(column_index(headers, &name)?);
+            }
+        }
+
+        let visible = items.len();
+        let mut order_by = Vec::new();
+        if parser.keyword("order") {
+            if !parser.keyword("by") {
+                anyhow::bail!("Expected ORDER BY");
+            }
+            loop {
+                let column = parse_order_key(&mut parser, &mut items, headers)?;
+                let desc = parser.keyword("desc");
+                if !desc {
+                    parser.keyword("asc");
+                }
+                order_by.push((column, desc));
+                if parser.peek() != Some(&Token::Comma) {
+                    break;
+                }
+                parser.next();
+            }
+        }
+
+        let limit = if parser.keyword("limit") {
+            match parser.next() {
+                Some(Token::Num(n)) => Some(n.parse()?),
+                t => anyhow::bail!("Expected a number after LIMIT, found {:?}", t),
+            }
+        } else {
+            None
+        };
+        if let Some(token) = parser.peek() {
+            anyhow::bail!("Unexpected {:?} in query", token);
+        }
+
+        let query = Self {
+            items,
+            visible,
+            filter,
+            group_by,
+            order_by,
+            limit,
+        };
+        query.check_grouping(headers)?;
+        Ok(query)
+    }
+
+    fn aggregated(&self) -> bool {
+        !self.group_by.is_empty()
+            || self
+                .items
+                .iter()
+                .any(|item| matches!(item.kind, ItemKind::Aggregate(..)))
+    }
+
+    /// plain columns of an aggregated query must be grouped on
+    fn check_grouping(&self, headers: &[String]) -> anyhow::Result<()> {
+        if !self.aggregated() {
+            return Ok(());
+        }
+        for item in &self.items {
+            if let ItemKind::Column(i) = item.kind {
+                if !self.group_by.contains(&i) {
+                    anyhow::bail!("Column {:?} must appear in GROUP BY", headers[i]);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Run the query, returns the output column names and rows
+    pub fn run(&self, reader: &mut CsvSource) -> anyhow::Result<(Vec<String>, Vec<StringRecord>)> {
+        let names = self.items[..self.visible]
+            .iter()
+            .map(|item| item.name.clone())
+            .collect();
+        // without sorting or grouping the scan can stop once the limit is reached
+        let early_limit = if self.order_by.is_empty() && !self.aggregated() {
+            self.limit
+        } else {
+            None
+        };
+
+        let mut groups: HashMap<Vec<String>, Vec<AggregateState>> = HashMap::new();
+        let mut group_order = Vec::new();
+        let mut rows = Vec::new();
+        let mut record = StringRecord::new();
+        while reader.read_record(&mut record)? {
+            if early_limit.is_some_and(|limit| rows.len() >= limit) {
+                break;
+            }
+            if let Some(filter) = &self.filter {
+                if !filter.eval(&record) {
+                    continue;
+                }
+            }
+            if !self.aggregated() {
+                rows.push(self.project(&record));
+                continue;
+            }
+            let key = self
+                .group_by
+                .iter()
+                .map(|&i| record.get(i).unwrap_or_default().to_string())
+                .collect::<Vec<_>>();
+            let states = groups.entry(key.clone()).or_insert_with(|| {
+                group_order.push(key);
+                self.items
+                    .iter()
+                    .map(|_| AggregateState::default())
+                    .collect()
+            });
+            for (item, state) in self.items.iter().zip(states.iter_mut()) {
+                if let ItemKind::Aggregate(_, column) = item.kind {
+                    state.update(column.map(|i| record.get(i).unwrap_or_default()));
+                }
+            }
+        }
+
+        if self.aggregated() {
+            // an aggregate without GROUP BY still yields one row on empty input
+            if group_order.is_empty() && self.group_by.is_empty() {
+                group_order.push(vec![]);
+                groups.insert(
+                    vec![],
+                    self.items
+                        .iter()
+                        .map(|_| AggregateState::default())
+                        .collect(),
+                );
+            }
+            for key in group_order {
+                let states = &groups[&key];
+                let row = self
+                    .items
+                    .iter()
+                    .zip(states)
+                    .map(|(item, state)| match item.kind {
+                        ItemKind::Column(i) => {
+                            let position = self.group_by.iter().position(|&g| g == i);
+                            position.map(|p| key[p].clone()).unwrap_or_default()
+                        }
+                        ItemKind::Aggregate(aggregate, _) => state.result(aggregate),
+                    });
+                rows.push(row.collect());
+            }
+        }
+
+        for &(column, desc) in self.order_by.iter().rev() {
+            // stable sorts applied from the last key to the first give a multi key order
+            rows.sort_by(|a: &StringRecord, b: &StringRecord| {
+                let ordering = compare_cells(
+                    a.get(column).unwrap_or_default(),
+                    b.get(column).unwrap_or_default(),
+                );
+                if desc {
+                    ordering.reverse()
+                } else {
+                    ordering
+                }
+            });
+        }
+        if let Some(limit) = self.limit {
+            rows.truncate(limit);
+        }
+        if self.visible < self.items.len() {
+            rows = rows
+                .iter()
+                .map(|row| row.iter().take(self.visible).collect())
+                .collect();
+        }
+        Ok((names, rows))
+    }
+
+    fn project(&self, record: &StringRecord) -> StringRecord {
+        self.items
+            .iter()
+            .map(|item| match item.kind {
+                ItemKind::Column(i) => record.get(i).unwrap_or_default(),
+                ItemKind::Aggregate(..) => "",
+            })
+            .collect()
+    }
+}
+
+/// `*` expands to every column
+fn parse_select_item(
+    parser: &mut ExprParser,
+    headers: &[String],
+) -> anyhow::Result<Vec<SelectItem>> {
+    if parser.peek() == Some(&Token::Star) {
+        parser.next();
+        return Ok(headers
+            .iter()
+            .enumerate()
+            .map(|(i, name)| SelectItem {
+                name: name.clone(),
+                text: name.clone(),
+                kind: ItemKind::Column(i),
+            })
+            .collect());
+    }
+    let name = match parser.next() {
+        Some(Token::Ident(name)) => name.clone(),
+        t => anyhow::bail!("Expected a column or aggregate, found {:?}", t),
+    };
+    let aggregate = match name.to_ascii_lowercase().as_str() {
+        "count" => Some(Aggregate::Count),
+        "sum" => Some(Aggregate::Sum),
+        "avg" => Some(Aggregate::Avg),
+        "min" => Some(Aggregate::Min),
+        "max" => Some(Aggregate::Max),
+        _ => None,
+    };
+    let item = match aggregate {
+        Some(aggregate) if parser.peek() == Some(&Token::LParen) => {
+            parser.next();
+            let (column, arg) = match parser.next() {
+                Some(Token::Star) if aggregate == Aggregate::Count => (None, "*".to_string()),
+                Some(Token::Ident(column)) => {
+                    (Some(column_index(headers, column)?), column.clone())
+                }
+                t => anyhow::bail!("Invalid argument {:?} for {}", t, name),
+            };
+            parser.expect(&Token::RParen)?;
+            (
+                format!("{}({})", name.to_ascii_lowercase(), arg),
+                ItemKind::Aggregate(aggregate, column),
+            )
+        }
+        _ => (
+            name.clone(),
+            ItemKind::Column(column_index(headers, &name)?),
+        ),
+    };
+    let (text, kind) = item;
+    let name = if parser.keyword("as") {
+        match parser.next() {
+            Some(Token::Ident(alias)) | Some(Token::Str(alias)) => alias.clone(),
+            t => anyhow::bail!("Expected an alias, found {:?}", t),
+        }
+    } else {
+        text.clone()
+    };
+    Ok(vec![SelectItem { name, text, kind }])
+}
+
+fn parse_names(parser: &mut ExprParser) -> anyhow::Result<Vec<String>> {
+    let mut names = Vec::new();
+    loop {
+        match parser.next() {
+            Some(Token::Ident(name)) => names.push(name.clone()),
+            t => anyhow::bail!("Expected a column, found {:?}", t),
+        }
+        if parser.peek() != Some(&Token::Comma) {
+            return Ok(names);
+        }
+        parser.next();
+    }
+}
+
+/// an order key is an output column name or alias, `count(*)` style aggregate,
+/// or a 1-based output position. Other input columns are added as hidden items.
+fn parse_order_key(
+    parser: &mut ExprParser,
+    items: &mut Vec<SelectItem>,
+    headers: &[String],
+) -> anyhow::Result<usize> {
+    let name = match parser.next() {
+        Some(Token::Num(n)) => {
+            let position = n.parse::<usize>()?;
+            if position == 0 || position > items.len() {
+                anyhow::bail!("ORDER BY position {} is out of range", position);
+            }
+            return Ok(position - 1);
+        }
+        Some(Token::Ident(name)) if parser.peek() == Some(&Token::LParen) => {
+            parser.next();
+            let arg = match parser.next() {
+                Some(Token::Star) => "*".to_string(),
+                Some(Token::Ident(column)) => column.clone(),
+                t => anyhow::bail!("Invalid argument {:?} for {}", t, name),
+            };
+            parser.expect(&Token::RParen)?;
+            format!("{}({})", name.to_ascii_lowercase(), arg)
+        }
+        Some(Token::Ident(name)) => name.clone(),
+        t => anyhow::bail!("Expected an ORDER BY column, found {:?}", t),
+    };
+    if let Some(i) = items
+        .iter()
+        .position(|item| item.name == name)
+        .or_else(|| items.iter().position(|item| item.text == name))
+    {
+        return Ok(i);
+    }
+    let column = headers
+        .iter()
+        .position(|h| *h == name)
+        .ok_or_else(|| anyhow::anyhow!("ORDER BY {:?} is not a selected column", name))?;
+    items.push(SelectItem {
+        name: name.clone(),
+        text: name,
+        kind: ItemKind::Column(column),
+    });
+    Ok(items.len() - 1)
+}
+
+/// Running aggregate of one select item within one group
+#[derive(Debug, Default)]
+struct AggregateState {
+    count: u64,
+    numbers: u64,
+    sum: f64,
+    min: Option<String>,
+    max: Option<String>,
+}
+
+impl AggregateState {
+    /// `None` is `count(*)`, which counts rows, the others skip empty cells
+    fn update(&mut self, cell: Option<&str>) {
+        let Some(cell) = cell else {
+            self.count += 1;
+            return;
+        };
+        if cell.is_empty() {
+            return;
+        }
+        self.count += 1;
+        if let Ok(x) = cell.parse::<f64>() {
+            self.numbers += 1;
+            self.sum += x;
+        }
+        if self
+            .min
+            .as_deref()
+            .is_none_or(|min| compare_cells(cell, min) == Ordering::Less)
+        {
+            self.min = Some(cell.to_string());
+        }
+        if self
+            .max
+            .as_deref()
+            .is_none_or(|max| compare_cells(cell, max) == Ordering::Greater)
+        {
+            self.max = Some(cell.to_string());
+        }
+    }
+
+    fn result(&self, aggregate: Aggregate) -> String {
+        match aggregate {
+            Aggregate::Count => self.count.to_string(),
+            Aggregate::Sum if self.numbers > 0 => self.sum.to_string(),
+            Aggregate::Avg if self.numbers > 0 => (self.sum / self.numbers as f64).to_string(),
+            Aggregate::Min => self.min.clone().unwrap_or_default(),
+            Aggregate::Max => self.max.clone().unwrap_or_default(),
+            _ => String::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::CsvReaderOpts;
+    use clap::Parser;
+
+    fn query(sql: &str) -> anyhow::Result<Vec<Vec<String>>> {
+        query_file("assets/juventus.csv", sql)
+    }
+
+    fn query_file(input: &str, sql: &str) -> anyhow::Result<Vec<Vec<String>>> {
+        let opts = CsvReaderOpts::parse_from(["query"]);
+        let mut reader = CsvSource::open(input, &opts)?;
+        let query = Query::parse(sql, &reader.headers)?;
+        let (_, rows) = query.run(&mut reader)?;
+        Ok(rows
+            .iter()
+            .map(|row| row.iter().map(String::from).collect())
+            .collect())
+    }
+
+    #[test]
+    fn test_group_by() -> anyhow::Result<()> {
+        let rows = query(
+            "SELECT Nationality, count(*) AS n, max(`Kit Number`) FROM players \
+             GROUP BY Nationality ORDER BY n DESC, Nationality LIMIT 2",
+        )?;
+        assert_eq!(rows, [["Italy", "8", "77"], ["Brazil", "3", "13"]]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_select_where() -> anyhow::Result<()> {
+        let rows = query(
+            "select Name from players where Position = 'Goalkeeper' order by `Kit Number` limit 2",
+        )?;
+        assert_eq!(rows, [["Wojciech Szczesny"], ["Carlo Pinsoglio"]]);
+        let rows = query("SELECT count(*), avg(`Kit Number`) FROM t WHERE Nationality = 'Wales'")?;
+        assert_eq!(rows, [["1", "8"]]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_order_by_mixed_column() -> anyhow::Result<()> {
+        let file = tempfile::NamedTempFile::new()?;
+        std::fs::write(
+            file.path(),
+            "id,v\n1,NaN\n2,10\n3,abc\n4,\n5,-2.5\n6,inf\n7,9\n8,NaN\n",
+        )?;
+        let path = file.path().to_str().unwrap();
+        let column = |sql: &str| -> anyhow::Result<Vec<String>> {
+            Ok(query_file(path, sql)?.into_iter().flatten().collect())
+        };
+        let ascending = column("SELECT v FROM t ORDER BY v")?;
+        assert_eq!(
+            ascending,
+            ["-2.5", "9", "10", "", "NaN", "NaN", "abc", "inf"]
+        );
+        let mut descending = column("SELECT v FROM t ORDER BY v DESC")?;
+        descending.reverse();
+        assert_eq!(descending, ascending);
+        Ok(())
+    }
+
+    #[test]
+    fn test_invalid_queries() {
+        assert!(query("SELECT Name, count(*) FROM t").is_err());
+        assert!(query("SELECT Age FROM t").is_err());
+        assert!(query("SELECT Name FROM t ORDER BY Age").is_err());
+        assert!(query("SELECT count(*) FROM t ORDER BY Position").is_err());
+        assert!(query("SELECT Name t").is_err());
+    }
+}
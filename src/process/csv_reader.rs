@@ -0,0 +1,244 @@
+use csv::{Reader, ReaderBuilder, StringRecord};
+
+use std::{
+    fs::File,
+    io::{Cursor, Read},
+};
+
+use crate::cli::CsvReaderOpts;
+
+use super::{
+    csv_encoding::utf8_reader,
+    csv_excel::{is_workbook, read_sheet},
+    csv_infer::ColumnType,
+};
+
+/// how much of the input `--auto` looks at to guess the dialect
+const SNIFF_SIZE: usize = 8 * 1024;
+const DELIMITERS: [u8; 5] = [b',', b';', b'\t', b'|', b':'];
+const QUOTES: [u8; 2] = [b'"', b'\''];
+
+/// A CSV input set up from the reader options, with resolved column names.
+/// Every command reading CSV goes through this so they all agree on the dialect
+/// and on the header handling. Spreadsheets are read through it as well.
+pub struct CsvSource {
+    records: Records,
+    pub headers: Vec<String>,
+    /// column types known from the input itself, the cell types of a spreadsheet
+    pub types: Option<Vec<ColumnType>>,
+    /// first data row, read ahead to learn the width of a headerless file
+    first: Option<StringRecord>,
+}
+
+enum Records {
+    Csv(Reader<Box<dyn Read>>),
+    Sheet(std::vec::IntoIter<StringRecord>),
+}
+
+/// Guessed CSV dialect
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Dialect {
+    pub delimiter: u8,
+    pub quote: u8,
+}
+
+impl CsvSource {
+    pub fn open(input: &str, opts: &CsvReaderOpts) -> anyhow::Result<Self> {
+        if is_workbook(input) {
+            let sheet = read_sheet(input, opts)?;
+            let width = sheet.rows.first().map_or(0, |r| r.len());
+            return Ok(Self {
+                records: Records::Sheet(sheet.rows.into_iter()),
+                headers: column_names(&sheet.header_row, &opts.columns, width),
+                types: Some(sheet.types),
+                first: None,
+            });
+        }
+        if opts.sheet.is_some() || opts.header_row.is_some() {
+            anyhow::bail!("--sheet and --header-row only apply to xlsx, xls or ods input");
+        }
+
+        let file: Box<dyn Read> = if input == "-" {
+            Box::new(std::io::stdin())
+        } else {
+            Box::new(File::open(input)?)
+        };
+        let mut file = utf8_reader(file, &opts.encoding)?;
+        let mut dialect = Dialect {
+            delimiter: ascii_byte(opts.delimiter)?,
+            quote: ascii_byte(opts.quote)?,
+        };
+        if opts.auto {
+            let mut prefix = Vec::with_capacity(SNIFF_SIZE);
+            file.by_ref()
+                .take(SNIFF_SIZE as u64)
+                .read_to_end(&mut prefix)?;
+            dialect = sniff_dialect(&prefix, prefix.len() < SNIFF_SIZE);
+            // hand the sniffed bytes back in front of the rest of the file
+            file = Box::new(Cursor::new(prefix).chain(file));
+        }
+
+        let mut reader = ReaderBuilder::new()
+            .has_headers(opts.header)
+            .delimiter(dialect.delimiter)
+            .quote(dialect.quote)
+            .double_quote(opts.escape.is_none())
+            .escape(opts.escape.map(ascii_byte).transpose()?)
+            .comment(opts.comment.map(ascii_byte).transpose()?)
+            .flexible(opts.flexible)
+            .from_reader(file);
+
+        let header_row = if opts.header {
+            reader.headers()?.clone()
+        } else {
+            StringRecord::new()
+        };
+        let mut first = StringRecord::new();
+        let first = reader.read_record(&mut first)?.then_some(first);
+        let width = first.as_ref().map_or(0, |r| r.len());
+        let headers = column_names(&header_row, &opts.columns, width);
+        Ok(Self {
+            records: Records::Csv(reader),
+            headers,
+            types: None,
+            first,
+        })
+    }
+
+    /// Read the next data row into `record`, returns false at the end of input
+    pub fn read_record(&mut self, record: &mut StringRecord) -> anyhow::Result<bool> {
+        if let Some(first) = self.first.take() {
+            *record = first;
+            return Ok(true);
+        }
+        match &mut self.records {
+            Records::Csv(reader) => Ok(reader.read_record(record)?),
+            Records::Sheet(rows) => match rows.next() {
+                Some(row) => {
+                    *record = row;
+                    Ok(true)
+                }
+                None => Ok(false),
+            },
+        }
+    }
+}
+
+pub fn ascii_byte(c: char) -> anyhow::Result<u8> {
+    if c.is_ascii() {
+        Ok(c as u8)
+    } else {
+        Err(anyhow::anyhow!("{:?} is not a single byte character", c))
+    }
+}
+
+/// names given with `--columns` win over the header row, columns left without
+/// a name are called col0..colN after their position
+fn column_names(header_row: &StringRecord, columns: &[String], width: usize) -> Vec<String> {
+    let width = width.max(header_row.len()).max(columns.len());
+    (0..width)
+        .map(|i| {
+            columns
+                .get(i)
+                .map(String::as_str)
+                .or_else(|| header_row.get(i))
+                .map(String::from)
+                .unwrap_or_else(|| format!("col{}", i))
+        })
+        .collect()
+}
+
+/// Guess delimiter and quote from the head of a file. The delimiter is the one
+/// splitting the most rows into the same number (more than one) of fields.
+pub fn sniff_dialect(prefix: &[u8], complete: bool) -> Dialect {
+    // a truncated last line would skew the field counts
+    let text = match prefix.iter().rposition(|&b| b == b'\n') {
+        Some(end) if !complete => &prefix[..end],
+        _ => prefix,
+    };
+    let quote = sniff_quote(text);
+
+    let mut best = (b',', 0, 0);
+    for delimiter in DELIMITERS {
+        let mut reader = ReaderBuilder::new()
+            .has_headers(false)
+            .flexible(true)
+            .delimiter(delimiter)
+            .quote(quote)
+            .from_reader(text);
+        let counts = reader
+            .byte_records()
+            .map_while(Result::ok)
+            .map(|r| r.len())
+            .collect::<Vec<_>>();
+        let Some(&width) = counts.first() else {
+            continue;
+        };
+        let consistent = counts.iter().filter(|&&c| c == width).count();
+        if width > 1 && (consistent, width) > (best.1, best.2) {
+            best = (delimiter, consistent, width);
+        }
+    }
+    Dialect {
+        delimiter: best.0,
+        quote,
+    }
+}
+
+/// the quote char is the one most often opening a field
+fn sniff_quote(text: &[u8]) -> u8 {
+    let opening = |quote: u8| {
+        text.iter()
+            .enumerate()
+            .filter(|&(i, &b)| {
+                b == quote
+                    && (i == 0 || matches!(text[i - 1], b'\n' | b'\r') || {
+                        DELIMITERS.contains(&text[i - 1])
+                    })
+            })
+            .count()
+    };
+    QUOTES
+        .into_iter()
+        .max_by_key(|&q| (opening(q), q == b'"'))
+        .unwrap_or(b'"')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_column_names() {
+        let header = StringRecord::from(vec!["a", "b"]);
+        let columns = vec!["x".to_string()];
+        assert_eq!(column_names(&header, &[], 2), ["a", "b"]);
+        assert_eq!(column_names(&header, &columns, 3), ["x", "b", "col2"]);
+        assert_eq!(
+            column_names(&StringRecord::new(), &[], 3),
+            ["col0", "col1", "col2"]
+        );
+    }
+
+    #[test]
+    fn test_sniff_dialect() {
+        let semicolon = b"name;city;note\nPerin;Genova;\"a, b\"\nBuffon;Torino;x\n";
+        assert_eq!(
+            sniff_dialect(semicolon, true),
+            Dialect {
+                delimiter: b';',
+                quote: b'"'
+            }
+        );
+        let tab = b"name\tage\nPerin\t26\nBuffon\t41\nSzcz";
+        assert_eq!(sniff_dialect(tab, false).delimiter, b'\t');
+        let single = b"'a,b'|c\n'd'|e\n";
+        assert_eq!(
+            sniff_dialect(single, true),
+            Dialect {
+                delimiter: b'|',
+                quote: b'\''
+            }
+        );
+    }
+}
@@ -0,0 +1,244 @@
+use std::{
+    cmp::Ordering,
+    collections::BinaryHeap,
+    fs::File,
+    io::{BufReader, BufWriter, Seek, SeekFrom},
+    rc::Rc,
+};
+
+use csv::{ReaderBuilder, StringRecord, WriterBuilder};
+
+use super::csv_reader::CsvSource;
+
+pub type RecordOrder = Box<dyn Fn(&StringRecord, &StringRecord) -> Ordering>;
+
+type Records = Box<dyn Iterator<Item = anyhow::Result<StringRecord>>>;
+
+/// most runs merged at once, so at most this many temporary files are read
+/// together in one pass
+const MAX_FAN_IN: usize = 64;
+
+/// Records of a CSV input in sorted order. Inputs larger than the memory limit
+/// are sorted in chunks spilled to temporary files, which are then merged.
+/// Records comparing equal keep their input order.
+pub struct SortedRecords {
+    runs: Vec<Records>,
+    heads: BinaryHeap<Head>,
+    order: Rc<RecordOrder>,
+}
+
+/// The next record of one sorted run. The heap pops the greatest head, so the
+/// smallest record compares greatest and the earliest run wins ties, which
+/// keeps the merge stable.
+struct Head {
+    record: StringRecord,
+    run: usize,
+    order: Rc<RecordOrder>,
+}
+
+impl Ord for Head {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.order)(&other.record, &self.record).then(other.run.cmp(&self.run))
+    }
+}
+
+impl PartialOrd for Head {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Head {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Head {}
+
+/// Sort the remaining records of `reader`, holding about `max_bytes` of cell
+/// data in memory at once
+pub fn external_sort(
+    reader: &mut CsvSource,
+    max_bytes: usize,
+    order: RecordOrder,
+) -> anyhow::Result<SortedRecords> {
+    let order = Rc::new(order);
+    // levels[i] holds runs merged i times; a full level is merged into one run
+    // of the next, so older input always sits in higher levels
+    let mut levels: Vec<Vec<Records>> = Vec::new();
+    let mut chunk = Vec::new();
+    let mut bytes = 0;
+    let mut record = StringRecord::new();
+    while reader.read_record(&mut record)? {
+        bytes += record.as_slice().len() + record.len() * 8;
+        chunk.push(record.clone());
+        if bytes >= max_bytes {
+            chunk.sort_by(|a, b| order(a, b));
+            let mut run = spill(chunk.drain(..).map(Ok))?;
+            bytes = 0;
+            for level in 0.. {
+                if levels.len() == level {
+                    levels.push(Vec::new());
+                }
+                levels[level].push(run);
+                if levels[level].len() < MAX_FAN_IN {
+                    break;
+                }
+                let full = std::mem::take(&mut levels[level]);
+                run = spill(SortedRecords::new(full, order.clone())?)?;
+            }
+        }
+    }
+    chunk.sort_by(|a, b| order(a, b));
+    let mut runs = levels.into_iter().rev().flatten().collect::<Vec<_>>();
+    runs.push(Box::new(chunk.into_iter().map(Ok)));
+    while runs.len() > MAX_FAN_IN {
+        runs = merge_pass(runs, &order)?;
+    }
+    SortedRecords::new(runs, order)
+}
+
+/// merge each group of `MAX_FAN_IN` neighbouring runs into one
+fn merge_pass(runs: Vec<Records>, order: &Rc<RecordOrder>) -> anyhow::Result<Vec<Records>> {
+    let mut merged = Vec::new();
+    let mut runs = runs.into_iter().peekable();
+    while runs.peek().is_some() {
+        let group = runs.by_ref().take(MAX_FAN_IN).collect();
+        merged.push(spill(SortedRecords::new(group, order.clone())?)?);
+    }
+    Ok(merged)
+}
+
+/// write sorted records to an anonymous temporary file and read them back lazily
+fn spill(records: impl Iterator<Item = anyhow::Result<StringRecord>>) -> anyhow::Result<Records> {
+    let mut writer = WriterBuilder::new()
+        .flexible(true)
+        .from_writer(BufWriter::new(tempfile::tempfile()?));
+    for record in records {
+        writer.write_record(&record?)?;
+    }
+    let mut file: File = writer
+        .into_inner()
+        .map_err(|e| e.into_error())?
+        .into_inner()
+        .map_err(|e| e.into_error())?;
+    file.seek(SeekFrom::Start(0))?;
+    let reader = ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .from_reader(BufReader::new(file));
+    Ok(Box::new(
+        reader
+            .into_records()
+            .map(|r| r.map_err(anyhow::Error::from)),
+    ))
+}
+
+impl SortedRecords {
+    fn new(mut runs: Vec<Records>, order: Rc<RecordOrder>) -> anyhow::Result<Self> {
+        let mut heads = BinaryHeap::with_capacity(runs.len());
+        for (run, records) in runs.iter_mut().enumerate() {
+            if let Some(record) = records.next().transpose()? {
+                let order = order.clone();
+                heads.push(Head { record, run, order });
+            }
+        }
+        Ok(Self { runs, heads, order })
+    }
+}
+
+impl Iterator for SortedRecords {
+    type Item = anyhow::Result<StringRecord>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let head = self.heads.pop()?;
+        match self.runs[head.run].next().transpose() {
+            Ok(Some(record)) => self.heads.push(Head {
+                record,
+                run: head.run,
+                order: self.order.clone(),
+            }),
+            Ok(None) => {}
+            Err(e) => return Some(Err(e)),
+        }
+        Some(Ok(head.record))
+    }
+}
+
+/// Order records by the text of the key columns, the order both sides of a
+/// merge join have to agree on
+pub fn key_order(key: Vec<usize>) -> RecordOrder {
+    Box::new(move |a, b| compare_keys(a, &key, b, &key))
+}
+
+/// compare the key columns of two records, which may come from different files
+pub fn compare_keys(
+    a: &StringRecord,
+    a_key: &[usize],
+    b: &StringRecord,
+    b_key: &[usize],
+) -> Ordering {
+    a_key
+        .iter()
+        .zip(b_key)
+        .map(|(&i, &j)| {
+            a.get(i)
+                .unwrap_or_default()
+                .cmp(b.get(j).unwrap_or_default())
+        })
+        .find(|o| o.is_ne())
+        .unwrap_or(Ordering::Equal)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::CsvReaderOpts;
+    use clap::Parser;
+
+    #[test]
+    fn test_external_sort_spills() -> anyhow::Result<()> {
+        let opts = CsvReaderOpts::parse_from(["sort"]);
+        let mut reader = CsvSource::open("assets/juventus.csv", &opts)?;
+        // a tiny limit forces one temporary file every couple of rows
+        let sorted = external_sort(&mut reader, 100, key_order(vec![3]))?
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        let mut reader = CsvSource::open("assets/juventus.csv", &opts)?;
+        let mut expected = Vec::new();
+        let mut record = StringRecord::new();
+        while reader.read_record(&mut record)? {
+            expected.push(record.clone());
+        }
+        expected.sort_by(|a, b| a[3].cmp(&b[3]));
+        assert_eq!(sorted.len(), 27);
+        assert_eq!(sorted, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn test_multi_pass_merge_is_stable() -> anyhow::Result<()> {
+        // one run per row: a run merged twice plus just under two full levels,
+        // too many runs for one final merge
+        let rows = MAX_FAN_IN * MAX_FAN_IN + (MAX_FAN_IN - 1) * (MAX_FAN_IN + 1);
+        let mut input = String::from("key,row\n");
+        for row in 0..rows {
+            input.push_str(&format!("{},{}\n", (row * 7919) % 13, row));
+        }
+        let file = tempfile::NamedTempFile::new()?;
+        std::fs::write(file.path(), input)?;
+        let opts = CsvReaderOpts::parse_from(["sort"]);
+        let mut reader = CsvSource::open(file.path().to_str().unwrap(), &opts)?;
+        let sorted = external_sort(&mut reader, 1, key_order(vec![0]))?
+            .map(|r| r.map(|r| (r[0].to_string(), r[1].parse::<usize>().unwrap())))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        let mut expected = (0..rows)
+            .map(|row| (((row * 7919) % 13).to_string(), row))
+            .collect::<Vec<_>>();
+        expected.sort_by(|a, b| a.0.cmp(&b.0));
+        assert_eq!(sorted, expected);
+        Ok(())
+    }
+}
@@ -0,0 +1,422 @@
+use std::{
+    collections::{hash_map::DefaultHasher, BTreeSet, HashMap},
+    hash::{Hash, Hasher},
+    io::Write,
+};
+
+use csv::StringRecord;
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::{cli::CsvStatsOpts, writer_for_output};
+
+use super::{
+    csv_infer::{ColumnType, TypeInference},
+    csv_reader::CsvSource,
+    csv_writer::{value_to_cell, RecordWriter},
+    table::render_table,
+};
+
+/// Profile of one CSV column
+#[derive(Debug, Serialize)]
+pub struct ColumnStats {
+    pub column: String,
+    #[serde(rename = "type")]
+    pub column_type: ColumnType,
+    pub rows: u64,
+    pub nulls: u64,
+    pub distinct: u64,
+    pub min: Value,
+    pub max: Value,
+    pub mean: Option<f64>,
+    pub stddev: Option<f64>,
+    pub top: Vec<TopValue>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TopValue {
+    pub value: String,
+    pub count: u64,
+}
+
+pub fn process_csv_stats(opts: &CsvStatsOpts) -> anyhow::Result<()> {
+    let mut reader = CsvSource::open(&opts.input, &opts.reader)?;
+    let stats = column_stats(&mut reader, opts.top, opts.approx)?;
+
+    let mut writer = writer_for_output(opts.output.as_deref())?;
+    match opts.format {
+        Some(format) => {
+            let headers = STATS_COLUMNS.map(String::from).to_vec();
+            let mut records = RecordWriter::new(writer, format, headers);
+            for column in &stats {
+                records.write(&serde_json::to_value(column)?)?;
+            }
+            records.finish()?;
+        }
+        None => {
+            let headers = STATS_COLUMNS.map(String::from);
+            let rows = stats.iter().map(table_row).collect::<Vec<_>>();
+            writer.write_all(render_table(&headers, &rows).as_bytes())?;
+            writer.flush()?;
+        }
+    }
+    Ok(())
+}
+
+const STATS_COLUMNS: [&str; 10] = [
+    "column", "type", "rows", "nulls", "distinct", "min", "max", "mean", "stddev", "top",
+];
+
+fn table_row(stats: &ColumnStats) -> Vec<String> {
+    let float = |f: Option<f64>| f.map(|f| format!("{:.4}", f)).unwrap_or_default();
+    let top = stats
+        .top
+        .iter()
+        .map(|t| format!("{} ({})", t.value, t.count))
+        .collect::<Vec<_>>()
+        .join(", ");
+    vec![
+        stats.column.clone(),
+        stats.column_type.to_string(),
+        stats.rows.to_string(),
+        stats.nulls.to_string(),
+        stats.distinct.to_string(),
+        value_to_cell(&stats.min),
+        value_to_cell(&stats.max),
+        float(stats.mean),
+        float(stats.stddev),
+        top,
+    ]
+}
+
+/// Profile every column in a single pass over the input
+pub fn column_stats(
+    reader: &mut CsvSource,
+    top: usize,
+    approx: bool,
+) -> anyhow::Result<Vec<ColumnStats>> {
+    let mut columns = reader
+        .headers
+        .iter()
+        .map(|_| ColumnProfile::new(top, approx))
+        .collect::<Vec<_>>();
+    let mut rows = 0;
+    let mut record = StringRecord::new();
+    while reader.read_record(&mut record)? {
+        rows += 1;
+        for (i, column) in columns.iter_mut().enumerate() {
+            column.update(record.get(i).unwrap_or_default());
+        }
+    }
+    Ok(reader
+        .headers
+        .iter()
+        .zip(columns)
+        .map(|(name, column)| column.finish(name, rows, top))
+        .collect())
+}
+
+/// Running statistics of one column
+struct ColumnProfile {
+    inference: TypeInference,
+    nulls: u64,
+    values: Counter,
+    /// Welford running mean and sum of squared deviations of numeric cells
+    numbers: u64,
+    mean: f64,
+    m2: f64,
+    min_number: f64,
+    max_number: f64,
+    min_text: Option<String>,
+    max_text: Option<String>,
+}
+
+impl ColumnProfile {
+    fn new(top: usize, approx: bool) -> Self {
+        Self {
+            inference: TypeInference::default(),
+            nulls: 0,
+            values: if approx {
+                Counter::approximate(top)
+            } else {
+                Counter::Exact(HashMap::new())
+            },
+            numbers: 0,
+            mean: 0.0,
+            m2: 0.0,
+            min_number: f64::INFINITY,
+            max_number: f64::NEG_INFINITY,
+            min_text: None,
+            max_text: None,
+        }
+    }
+
+    fn update(&mut self, cell: &str) {
+        if cell.is_empty() {
+            self.nulls += 1;
+            return;
+        }
+        self.inference.update(cell);
+        self.values.add(cell);
+        if let Ok(x) = cell.parse::<f64>() {
+            if x.is_finite() {
+                self.numbers += 1;
+                let delta = x - self.mean;
+                self.mean += delta / self.numbers as f64;
+                self.m2 += delta * (x - self.mean);
+                self.min_number = self.min_number.min(x);
+                self.max_number = self.max_number.max(x);
+            }
+        }
+        if self.min_text.as_deref().is_none_or(|min| cell < min) {
+            self.min_text = Some(cell.to_string());
+        }
+        if self.max_text.as_deref().is_none_or(|max| cell > max) {
+            self.max_text = Some(cell.to_string());
+        }
+    }
+
+    fn finish(self, name: &str, rows: u64, top: usize) -> ColumnStats {
+        let column_type = self.inference.column_type();
+        let numeric = matches!(column_type, ColumnType::Integer | ColumnType::Float);
+        let (min, max, mean, stddev) = if numeric && self.numbers > 0 {
+            let stddev = if self.numbers > 1 {
+                Some((self.m2 / (self.numbers - 1) as f64).sqrt())
+            } else {
+                None
+            };
+            (
+                column_type.convert(&self.min_number.to_string()),
+                column_type.convert(&self.max_number.to_string()),
+                Some(self.mean),
+                stddev,
+            )
+        } else {
+            (
+                self.min_text.map(Value::String).unwrap_or_default(),
+                self.max_text.map(Value::String).unwrap_or_default(),
+                None,
+                None,
+            )
+        };
+        ColumnStats {
+            column: name.to_string(),
+            column_type,
+            rows,
+            nulls: self.nulls,
+            distinct: self.values.distinct(),
+            min,
+            max,
+            mean,
+            stddev,
+            top: self.values.top(top),
+        }
+    }
+}
+
+/// Value frequencies, exact or bounded in memory for large files
+enum Counter {
+    Exact(HashMap<String, u64>),
+    /// HyperLogLog for the distinct count, space saving summary for the top values
+    Approximate(HyperLogLog, SpaceSaving),
+}
+
+impl Counter {
+    fn approximate(top: usize) -> Self {
+        Counter::Approximate(HyperLogLog::new(14), SpaceSaving::new((top * 10).max(100)))
+    }
+
+    fn add(&mut self, value: &str) {
+        match self {
+            Counter::Exact(counts) => match counts.get_mut(value) {
+                Some(count) => *count += 1,
+                None => {
+                    counts.insert(value.to_string(), 1);
+                }
+            },
+            Counter::Approximate(hll, top) => {
+                hll.add(value);
+                top.add(value);
+            }
+        }
+    }
+
+    fn distinct(&self) -> u64 {
+        match self {
+            Counter::Exact(counts) => counts.len() as u64,
+            Counter::Approximate(hll, _) => hll.estimate().round() as u64,
+        }
+    }
+
+    fn top(&self, n: usize) -> Vec<TopValue> {
+        let counts: Box<dyn Iterator<Item = (&String, u64)>> = match self {
+            Counter::Exact(counts) => Box::new(counts.iter().map(|(value, count)| (value, *count))),
+            Counter::Approximate(_, top) => Box::new(top.counts()),
+        };
+        let mut top = counts
+            .map(|(value, count)| TopValue {
+                value: value.clone(),
+                count,
+            })
+            .collect::<Vec<_>>();
+        top.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.value.cmp(&b.value)));
+        top.truncate(n);
+        top
+    }
+}
+
+/// HyperLogLog cardinality estimator with 2^p registers
+pub struct HyperLogLog {
+    p: u32,
+    registers: Vec<u8>,
+}
+
+impl HyperLogLog {
+    pub fn new(p: u32) -> Self {
+        Self {
+            p,
+            registers: vec![0; 1 << p],
+        }
+    }
+
+    pub fn add(&mut self, value: impl Hash) {
+        let mut hasher = DefaultHasher::new();
+        value.hash(&mut hasher);
+        let hash = hasher.finish();
+        let index = (hash >> (64 - self.p)) as usize;
+        // the sentinel bit bounds the rank when the remaining bits are all zero
+        let rest = (hash << self.p) | (1 << (self.p - 1));
+        let rank = rest.leading_zeros() as u8 + 1;
+        self.registers[index] = self.registers[index].max(rank);
+    }
+
+    pub fn estimate(&self) -> f64 {
+        let m = self.registers.len() as f64;
+        let alpha = 0.7213 / (1.0 + 1.079 / m);
+        let sum = self
+            .registers
+            .iter()
+            .map(|&r| 2f64.powi(-(r as i32)))
+            .sum::<f64>();
+        let estimate = alpha * m * m / sum;
+        let zeros = self.registers.iter().filter(|&&r| r == 0).count();
+        // linear counting is more accurate for small cardinalities
+        if estimate <= 2.5 * m && zeros > 0 {
+            m * (m / zeros as f64).ln()
+        } else {
+            estimate
+        }
+    }
+}
+
+/// Space saving heavy hitters: keeps at most `capacity` counters, a new value
+/// takes over the smallest counter when full. Counters are ordered by count,
+/// so every update is O(log capacity).
+struct SpaceSaving {
+    capacity: usize,
+    /// slot of every tracked value
+    slots: HashMap<String, usize>,
+    counters: Vec<(String, u64)>,
+    /// (count, slot) of every counter, the first is the smallest
+    order: BTreeSet<(u64, usize)>,
+}
+
+impl SpaceSaving {
+    fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            slots: HashMap::with_capacity(capacity),
+            counters: Vec::with_capacity(capacity),
+            order: BTreeSet::new(),
+        }
+    }
+
+    fn add(&mut self, value: &str) {
+        if let Some(&slot) = self.slots.get(value) {
+            let count = &mut self.counters[slot].1;
+            self.order.remove(&(*count, slot));
+            *count += 1;
+            self.order.insert((*count, slot));
+            return;
+        }
+        if self.counters.len() < self.capacity {
+            let slot = self.counters.len();
+            self.slots.insert(value.to_string(), slot);
+            self.counters.push((value.to_string(), 1));
+            self.order.insert((1, slot));
+            return;
+        }
+        let Some((min, slot)) = self.order.pop_first() else {
+            return;
+        };
+        let (old, count) = &mut self.counters[slot];
+        self.slots.remove(old.as_str());
+        *old = value.to_string();
+        *count = min + 1;
+        self.slots.insert(value.to_string(), slot);
+        self.order.insert((min + 1, slot));
+    }
+
+    fn counts(&self) -> impl Iterator<Item = (&String, u64)> {
+        self.counters.iter().map(|(value, count)| (value, *count))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::CsvReaderOpts;
+    use clap::Parser;
+
+    #[test]
+    fn test_hyperloglog_estimate() {
+        let mut hll = HyperLogLog::new(14);
+        for i in 0..50_000 {
+            hll.add(format!("value-{}", i % 20_000));
+        }
+        assert!((hll.estimate() - 20_000.0).abs() / 20_000.0 < 0.03);
+    }
+
+    #[test]
+    fn test_space_saving() {
+        let mut top = SpaceSaving::new(20);
+        // two heavy values among 10k distinct ones, both more frequent than
+        // total / capacity so both are guaranteed a counter
+        for i in 0..10_000 {
+            top.add(&format!("noise-{}", i));
+            if i % 4 == 0 {
+                top.add("Italy");
+            }
+            if i % 10 == 0 {
+                top.add("Poland");
+            }
+        }
+        let counts = top.counts().collect::<HashMap<_, _>>();
+        assert_eq!(counts.len(), 20);
+        // space saving never undercounts a tracked value
+        assert!(counts[&"Italy".to_string()] >= 2_500);
+        assert!(counts[&"Poland".to_string()] >= 1_000);
+        assert_eq!(top.order.len(), 20);
+        assert!(top
+            .order
+            .iter()
+            .all(|&(count, slot)| top.counters[slot].1 == count));
+    }
+
+    #[test]
+    fn test_column_stats() -> anyhow::Result<()> {
+        let opts = CsvReaderOpts::parse_from(["stats"]);
+        let mut reader = CsvSource::open("assets/juventus.csv", &opts)?;
+        let stats = column_stats(&mut reader, 2, false)?;
+        let kit = &stats[4];
+        assert_eq!(kit.column, "Kit Number");
+        assert_eq!(kit.column_type, ColumnType::Integer);
+        assert_eq!(kit.nulls, 0);
+        assert_eq!(kit.distinct, kit.rows);
+        assert_eq!(kit.min, Value::from(1));
+        let nationality = &stats[3];
+        assert_eq!(nationality.top[0].value, "Italy");
+        assert_eq!(nationality.top.len(), 2);
+        Ok(())
+    }
+}
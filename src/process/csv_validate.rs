@@ -0,0 +1,386 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    io::Write,
+};
+
+use csv::StringRecord;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+use crate::{cli::CsvValidateOpts, writer_for_output};
+
+use super::{
+    csv_filter::compare_cells,
+    csv_infer::{finite_number, ColumnType},
+    csv_reader::CsvSource,
+    csv_writer::RecordWriter,
+    table::render_table,
+};
+
+/// Validation schema, a yaml or json document such as
+///
+/// ```yaml
+/// columns:
+///   Name: { required: true, nullable: false, unique: true }
+///   Position: { enum: [Goalkeeper, Centre-Back] }
+///   Kit Number: { type: integer, min: 1, max: 99 }
+///   DOB: { pattern: '^[A-Z][a-z]{2} \d{1,2}, \d{4}' }
+/// ```
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ValidationSchema {
+    pub columns: HashMap<String, ColumnRule>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ColumnRule {
+    #[serde(rename = "type")]
+    pub column_type: Option<ColumnType>,
+    /// the column must be in the header
+    #[serde(default)]
+    pub required: bool,
+    /// empty cells are allowed
+    #[serde(default = "default_nullable")]
+    pub nullable: bool,
+    /// regex every non-empty cell must match
+    pub pattern: Option<String>,
+    #[serde(rename = "enum")]
+    pub allowed: Option<Vec<String>>,
+    /// inclusive bounds, numeric when both sides are numbers
+    pub min: Option<serde_yaml::Value>,
+    pub max: Option<serde_yaml::Value>,
+    #[serde(default)]
+    pub unique: bool,
+}
+
+fn default_nullable() -> bool {
+    true
+}
+
+impl ValidationSchema {
+    /// Parse a yaml or json schema, a `min` or `max` that is neither a number
+    /// nor a string is an error rather than a rule quietly left out
+    pub fn parse(text: &str) -> anyhow::Result<Self> {
+        let schema: Self = serde_yaml::from_str(text)?;
+        for (name, rule) in &schema.columns {
+            for (bound, value) in [("min", &rule.min), ("max", &rule.max)] {
+                if let Some(value) = value.as_ref().filter(|v| bound_text(v).is_none()) {
+                    anyhow::bail!(
+                        "Column {:?}: {} must be a number or a string, not {:?}",
+                        name,
+                        bound,
+                        value
+                    );
+                }
+            }
+        }
+        Ok(schema)
+    }
+}
+
+/// a `min` or `max` bound as the cell text it is compared with
+fn bound_text(value: &serde_yaml::Value) -> Option<String> {
+    match value {
+        serde_yaml::Value::String(s) => Some(s.clone()),
+        serde_yaml::Value::Number(n) => Some(n.to_string()),
+        _ => None,
+    }
+}
+
+/// One broken rule, row and line are absent for header problems
+#[derive(Debug, Serialize, PartialEq)]
+pub struct Violation {
+    pub row: Option<u64>,
+    pub line: Option<u64>,
+    pub column: String,
+    pub rule: &'static str,
+    pub value: Option<String>,
+    pub message: String,
+}
+
+const VIOLATION_COLUMNS: [&str; 6] = ["row", "line", "column", "rule", "value", "message"];
+
+pub fn process_csv_validate(opts: &CsvValidateOpts) -> anyhow::Result<()> {
+    let schema = ValidationSchema::parse(&fs::read_to_string(&opts.schema)?)?;
+    let mut reader = CsvSource::open(&opts.input, &opts.reader)?;
+    let violations = validate(&mut reader, &schema)?;
+
+    let mut writer = writer_for_output(opts.output.as_deref())?;
+    match opts.format {
+        Some(format) => {
+            let headers = VIOLATION_COLUMNS.map(String::from).to_vec();
+            let mut records = RecordWriter::new(writer, format, headers);
+            for violation in &violations {
+                records.write(&serde_json::to_value(violation)?)?;
+            }
+            records.finish()?;
+        }
+        None if violations.is_empty() => {}
+        None => {
+            let headers = VIOLATION_COLUMNS.map(String::from);
+            let rows = violations.iter().map(table_row).collect::<Vec<_>>();
+            writer.write_all(render_table(&headers, &rows).as_bytes())?;
+            writer.flush()?;
+        }
+    }
+    if !violations.is_empty() {
+        anyhow::bail!("{} schema violation(s) in {}", violations.len(), opts.input);
+    }
+    Ok(())
+}
+
+fn table_row(violation: &Violation) -> Vec<String> {
+    let number = |n: Option<u64>| n.map(|n| n.to_string()).unwrap_or_default();
+    vec![
+        number(violation.row),
+        number(violation.line),
+        violation.column.clone(),
+        violation.rule.to_string(),
+        violation.value.clone().unwrap_or_default(),
+        violation.message.clone(),
+    ]
+}
+
+/// a rule resolved against the header of the file being checked
+struct ColumnCheck<'a> {
+    index: usize,
+    name: &'a str,
+    rule: &'a ColumnRule,
+    pattern: Option<Regex>,
+    min: Option<String>,
+    max: Option<String>,
+    /// first row each value was seen on, for unique columns
+    seen: HashMap<String, u64>,
+}
+
+/// Check every row against the schema, returns all violations in input order
+pub fn validate(
+    reader: &mut CsvSource,
+    schema: &ValidationSchema,
+) -> anyhow::Result<Vec<Violation>> {
+    let mut violations = Vec::new();
+    // the reader is borrowed mutably below
+    let header_row = reader.headers.clone();
+    let headers = header_row
+        .iter()
+        .map(String::as_str)
+        .collect::<HashSet<_>>();
+    let mut missing = schema
+        .columns
+        .iter()
+        .filter(|(name, rule)| rule.required && !headers.contains(name.as_str()))
+        .map(|(name, _)| name)
+        .collect::<Vec<_>>();
+    missing.sort();
+    for name in missing {
+        violations.push(Violation {
+            row: None,
+            line: None,
+            column: name.clone(),
+            rule: "required",
+            value: None,
+            message: "required column is missing".to_string(),
+        });
+    }
+
+    let mut checks = Vec::new();
+    for (index, name) in header_row.iter().enumerate() {
+        let Some(rule) = schema.columns.get(name) else {
+            continue;
+        };
+        checks.push(ColumnCheck {
+            index,
+            name,
+            rule,
+            pattern: rule.pattern.as_deref().map(Regex::new).transpose()?,
+            min: rule.min.as_ref().and_then(bound_text),
+            max: rule.max.as_ref().and_then(bound_text),
+            seen: HashMap::new(),
+        });
+    }
+
+    let mut record = StringRecord::new();
+    let mut row = 0;
+    while reader.read_record(&mut record)? {
+        row += 1;
+        let line = record.position().map(|p| p.line());
+        for check in checks.iter_mut() {
+            let cell = record.get(check.index).unwrap_or_default();
+            for (rule, message) in check.check(cell, row) {
+                violations.push(Violation {
+                    row: Some(row),
+                    line,
+                    column: check.name.to_string(),
+                    rule,
+                    value: Some(cell.to_string()),
+                    message,
+                });
+            }
+        }
+    }
+    Ok(violations)
+}
+
+impl ColumnCheck<'_> {
+    /// broken rules of one cell as rule name and message
+    fn check(&mut self, cell: &str, row: u64) -> Vec<(&'static str, String)> {
+        let mut broken = Vec::new();
+        if cell.is_empty() {
+            if !self.rule.nullable {
+                broken.push(("nullable", "value is empty".to_string()));
+            }
+            return broken;
+        }
+        if let Some(t) = self.rule.column_type {
+            if !t.matches(cell) {
+                broken.push(("type", format!("expected {}", t)));
+            }
+        }
+        if let Some(pattern) = &self.pattern {
+            if !pattern.is_match(cell) {
+                broken.push(("pattern", format!("does not match {}", pattern)));
+            }
+        }
+        if let Some(allowed) = &self.rule.allowed {
+            if !allowed.iter().any(|v| v == cell) {
+                broken.push(("enum", format!("expected one of {}", allowed.join(", "))));
+            }
+        }
+        // a numeric bound only holds numbers, NaN and inf included
+        let not_number =
+            |bound: &str| finite_number(bound).is_some() && finite_number(cell).is_none();
+        if let Some(min) = &self.min {
+            if not_number(min) {
+                broken.push(("min", format!("not a number, expected at least {}", min)));
+            } else if compare_cells(cell, min).is_lt() {
+                broken.push(("min", format!("less than {}", min)));
+            }
+        }
+        if let Some(max) = &self.max {
+            if not_number(max) {
+                broken.push(("max", format!("not a number, expected at most {}", max)));
+            } else if compare_cells(cell, max).is_gt() {
+                broken.push(("max", format!("greater than {}", max)));
+            }
+        }
+        if self.rule.unique {
+            match self.seen.get(cell) {
+                Some(first) => broken.push(("unique", format!("duplicate of row {}", first))),
+                None => {
+                    self.seen.insert(cell.to_string(), row);
+                }
+            }
+        }
+        broken
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::CsvReaderOpts;
+    use clap::Parser;
+
+    #[test]
+    fn test_validate() -> anyhow::Result<()> {
+        let schema = ValidationSchema::parse(
+            r#"
+columns:
+  Name: { required: true, nullable: false, pattern: '^[A-Z]' }
+  Nationality: { enum: [Italy, Poland, Brazil, France, Germany, Portugal, Bosnia-Herzegovina, Argentina, Uruguay, Croatia, Netherlands, Colombia, Turkey] }
+  Kit Number: { type: integer, max: 50, unique: true }
+  Salary: { required: true }
+"#,
+        )?;
+        let opts = CsvReaderOpts::parse_from(["validate"]);
+        let mut reader = CsvSource::open("assets/juventus.csv", &opts)?;
+        let violations = validate(&mut reader, &schema)?;
+
+        assert_eq!(violations[0].column, "Salary");
+        assert_eq!(violations[0].rule, "required");
+        assert!(violations
+            .iter()
+            .all(|v| v.rule != "unique" && v.rule != "type"));
+        let wales = violations.iter().find(|v| v.rule == "enum").unwrap();
+        assert_eq!(wales.value.as_deref(), Some("Wales"));
+        let buffon = violations.iter().find(|v| v.rule == "max").unwrap();
+        assert_eq!(buffon.value.as_deref(), Some("77"));
+        assert_eq!(buffon.line, Some(buffon.row.unwrap() + 1));
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_cell() {
+        let rule: ColumnRule =
+            serde_yaml::from_str("{ type: integer, nullable: false, min: 1, unique: true }")
+                .unwrap();
+        let mut check = ColumnCheck {
+            index: 0,
+            name: "n",
+            rule: &rule,
+            pattern: None,
+            min: Some("1".to_string()),
+            max: None,
+            seen: HashMap::new(),
+        };
+        assert!(check.check("3", 1).is_empty());
+        assert_eq!(check.check("", 2)[0].0, "nullable");
+        assert_eq!(
+            check.check("3", 3),
+            [("unique", "duplicate of row 1".to_string())]
+        );
+        let rules = check.check("0.5", 4);
+        assert_eq!(
+            rules.iter().map(|r| r.0).collect::<Vec<_>>(),
+            ["type", "min"]
+        );
+        for (row, cell) in [(5, "NaN"), (6, "inf"), (7, "-infinity"), (8, "abc")] {
+            let rules = check.check(cell, row);
+            assert_eq!(
+                rules.iter().map(|r| r.0).collect::<Vec<_>>(),
+                ["type", "min"],
+                "{}",
+                cell
+            );
+            assert_eq!(rules[1].1, "not a number, expected at least 1");
+        }
+
+        // text bounds still compare as text
+        let rule: ColumnRule = serde_yaml::from_str("{ max: 'm' }").unwrap();
+        let mut check = ColumnCheck {
+            index: 0,
+            name: "n",
+            rule: &rule,
+            pattern: None,
+            min: None,
+            max: Some("m".to_string()),
+            seen: HashMap::new(),
+        };
+        assert!(check.check("inf", 1).is_empty());
+        assert_eq!(check.check("NaN", 2), []);
+        assert_eq!(check.check("z", 3)[0].0, "max");
+    }
+
+    #[test]
+    fn test_schema_bounds() {
+        for (rule, bound) in [
+            ("{ min: true }", "min"),
+            ("{ max: [1, 2] }", "max"),
+            ("{ min: 1, max: { x: 1 } }", "max"),
+        ] {
+            let err = ValidationSchema::parse(&format!("columns:\n  Kit Number: {}", rule))
+                .unwrap_err()
+                .to_string();
+            assert!(
+                err.starts_with(&format!("Column \"Kit Number\": {} must be", bound)),
+                "{}",
+                err
+            );
+        }
+        assert!(
+            ValidationSchema::parse("columns:\n  DOB: { min: '1980-01-01', max: 2.5 }").is_ok()
+        );
+    }
+}
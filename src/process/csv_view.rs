@@ -0,0 +1,124 @@
+use std::io::Write;
+
+use csv::StringRecord;
+
+use crate::{cli::CsvViewOpts, writer_for_output};
+
+use super::{
+    csv_infer::{infer_column_types, ColumnType},
+    csv_reader::CsvSource,
+    table::{column_widths, render_rows, ColumnLayout},
+};
+
+pub fn process_csv_view(opts: &CsvViewOpts) -> anyhow::Result<()> {
+    let mut writer = writer_for_output(None)?;
+    writer.write_all(render_page(opts)?.as_bytes())?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// the table of one page and the footer telling where it is in the input
+fn render_page(opts: &CsvViewOpts) -> anyhow::Result<String> {
+    let mut reader = CsvSource::open(&opts.input, &opts.reader)?;
+    // a page past any input is just empty, whatever --page and --rows multiply to
+    let first = (opts.page - 1).saturating_mul(opts.rows);
+    let last = first.saturating_add(opts.rows);
+
+    // read until both the width sample and the page are complete, plus one row
+    // to tell whether more follow
+    let mut sample = Vec::new();
+    let mut page = Vec::new();
+    let mut record = StringRecord::new();
+    let mut n = 0;
+    while (n < opts.sample || n <= last) && reader.read_record(&mut record)? {
+        if n < opts.sample {
+            sample.push(record.clone());
+        }
+        if (first..last).contains(&n) {
+            page.push(record.clone());
+        }
+        n += 1;
+    }
+    let more = n > last;
+
+    let types = match &reader.types {
+        Some(types) => types.clone(),
+        None => infer_column_types(reader.headers.len(), &sample),
+    };
+    let sample = sample.iter().map(cells).collect::<Vec<_>>();
+    let layout = column_widths(&reader.headers, &sample, opts.max_width.max(1))
+        .into_iter()
+        .enumerate()
+        .map(|(i, width)| ColumnLayout {
+            width,
+            right: matches!(types.get(i), Some(ColumnType::Integer | ColumnType::Float)),
+        })
+        .collect::<Vec<_>>();
+    let rows = page.iter().map(cells).collect::<Vec<_>>();
+
+    let table = render_rows(&reader.headers, &rows, &layout);
+    let footer = match (rows.len(), more) {
+        (0, _) => format!("no rows on page {}", opts.page),
+        (len, true) => format!(
+            "rows {}-{}, more on page {}",
+            first + 1,
+            first + len,
+            opts.page.saturating_add(1)
+        ),
+        (len, false) => format!("rows {}-{} of {}", first + 1, first + len, n),
+    };
+    Ok(format!("{}({})\n", table, footer))
+}
+
+fn cells(record: &StringRecord) -> Vec<String> {
+    record.iter().map(String::from).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    fn page(args: &[&str]) -> anyhow::Result<String> {
+        let file = tempfile::NamedTempFile::new()?;
+        let mut csv = String::from("Name,Kit Number\n");
+        for (name, kit) in [("Buffon", 77), ("Chiellini", 3), ("Bonucci", 19)] {
+            csv.push_str(&format!("{},{}\n", name, kit));
+        }
+        std::fs::write(file.path(), csv)?;
+        let argv = ["view", file.path().to_str().unwrap()]
+            .into_iter()
+            .chain(args.iter().copied());
+        render_page(&CsvViewOpts::parse_from(argv))
+    }
+
+    fn footer(page: &str) -> &str {
+        page.lines().last().unwrap_or_default()
+    }
+
+    #[test]
+    fn test_paging() -> anyhow::Result<()> {
+        let first = page(&["-n", "2"])?;
+        assert!(first.contains("Buffon") && first.contains("Chiellini"));
+        assert!(!first.contains("Bonucci"));
+        assert_eq!(footer(&first), "(rows 1-2, more on page 2)");
+
+        let second = page(&["-n", "2", "--page", "2"])?;
+        assert!(second.contains("Bonucci") && !second.contains("Buffon"));
+        assert_eq!(footer(&second), "(rows 3-3 of 3)");
+
+        assert_eq!(footer(&page(&["-n", "3"])?), "(rows 1-3 of 3)");
+        assert_eq!(footer(&page(&["--page", "4"])?), "(no rows on page 4)");
+        Ok(())
+    }
+
+    #[test]
+    fn test_paging_overflow() -> anyhow::Result<()> {
+        let max = usize::MAX.to_string();
+        let far = page(&["-n", &max, "--page", &max])?;
+        assert_eq!(footer(&far), format!("(no rows on page {})", max));
+        assert_eq!(footer(&page(&["-n", &max])?), "(rows 1-3 of 3)");
+        assert!(CsvViewOpts::try_parse_from(["view", "-n", "0"]).is_err());
+        Ok(())
+    }
+}
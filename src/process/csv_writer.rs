@@ -0,0 +1,415 @@
+use serde::Serialize;
+use serde_json::{ser::PrettyFormatter, Map, Serializer, Value};
+
+use std::io::Write;
+
+use crate::{
+    cli::{CsvReaderOpts, OutputFormat},
+    writer_for_output,
+};
+
+use super::csv_reader::ascii_byte;
+
+/// Streams records to the underlying writer one at a time, producing the same
+/// document as serializing the whole record list at once.
+pub struct RecordWriter<W: Write> {
+    writer: W,
+    format: OutputFormat,
+    headers: Vec<String>,
+    count: usize,
+    buf: Vec<u8>,
+    /// encoding buffer reused across records
+    record: Vec<u8>,
+}
+
+impl<W: Write> RecordWriter<W> {
+    /// `headers` are the columns of the table formats (markdown, html), in order
+    pub fn new(writer: W, format: OutputFormat, headers: Vec<String>) -> Self {
+        Self {
+            writer,
+            format,
+            headers,
+            count: 0,
+            buf: Vec::with_capacity(1024),
+            record: Vec::with_capacity(1024),
+        }
+    }
+
+    pub fn write(&mut self, record: &Value) -> anyhow::Result<()> {
+        let mut encoded = std::mem::take(&mut self.record);
+        encoded.clear();
+        encode_record(self.format, &self.headers, record, &mut encoded)?;
+        self.write_encoded(&encoded)?;
+        self.record = encoded;
+        Ok(())
+    }
+
+    /// Write a record already encoded with [`encode_record`], so records can be
+    /// encoded on other threads and written here in order
+    pub fn write_encoded(&mut self, encoded: &[u8]) -> anyhow::Result<()> {
+        self.buf.clear();
+        if self.count == 0 {
+            self.head()?;
+        } else {
+            self.buf.extend_from_slice(match self.format {
+                OutputFormat::Json => b",\n",
+                OutputFormat::Toml => b"\n",
+                _ => b"",
+            });
+        }
+        self.writer.write_all(&self.buf)?;
+        self.writer.write_all(encoded)?;
+        self.count += 1;
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> anyhow::Result<W> {
+        self.buf.clear();
+        match (self.format, self.count) {
+            (OutputFormat::Json, 0) => self.buf.extend_from_slice(b"[]"),
+            (OutputFormat::Json, _) => self.buf.extend_from_slice(b"\n]"),
+            (OutputFormat::Yaml, 0) => self.buf.extend_from_slice(b"[]\n"),
+            (OutputFormat::Markdown, 0) | (OutputFormat::Csv, 0) => self.head()?,
+            (OutputFormat::Html, n) => {
+                if n == 0 {
+                    self.head()?;
+                }
+                self.buf
+                    .extend_from_slice(b"</tbody>\n</table>\n</body>\n</html>\n");
+            }
+            _ => {}
+        }
+        self.writer.write_all(&self.buf)?;
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+
+    /// everything before the first record
+    fn head(&mut self) -> anyhow::Result<()> {
+        let buf = &mut self.buf;
+        match self.format {
+            OutputFormat::Json => buf.extend_from_slice(b"[\n"),
+            OutputFormat::Markdown => {
+                let header = self.headers.iter().map(|h| escape_markdown(h));
+                buf.extend_from_slice(markdown_row(header).as_bytes());
+                let rule = self.headers.iter().map(|_| "---".to_string());
+                buf.extend_from_slice(markdown_row(rule).as_bytes());
+            }
+            OutputFormat::Csv => encode_csv_row(self.headers.iter().cloned(), buf)?,
+            OutputFormat::Html => {
+                buf.extend_from_slice(HTML_HEAD.as_bytes());
+                buf.extend_from_slice(b"<thead>\n<tr>");
+                for h in &self.headers {
+                    buf.extend_from_slice(format!("<th>{}</th>", escape_html(h)).as_bytes());
+                }
+                buf.extend_from_slice(b"</tr>\n</thead>\n<tbody>\n");
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+const HTML_HEAD: &str = r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<style>
+table { border-collapse: collapse; font-family: sans-serif; }
+th, td { border: 1px solid #ccc; padding: 4px 8px; text-align: left; }
+thead { background: #f0f0f0; }
+</style>
+</head>
+<body>
+<table>
+"#;
+
+/// Encode one record without any document framing, the output only depends on
+/// the record itself
+pub fn encode_record(
+    format: OutputFormat,
+    headers: &[String],
+    record: &Value,
+    buf: &mut Vec<u8>,
+) -> anyhow::Result<()> {
+    match format {
+        OutputFormat::Json => encode_json(record, buf)?,
+        OutputFormat::Yaml => encode_yaml(record, buf)?,
+        OutputFormat::Ndjson => {
+            serde_json::to_writer(&mut *buf, record)?;
+            buf.push(b'\n');
+        }
+        OutputFormat::Toml => encode_toml(record, buf)?,
+        OutputFormat::Markdown => {
+            let cells = cells(headers, record).map(|c| escape_markdown(&c));
+            buf.extend_from_slice(markdown_row(cells).as_bytes());
+        }
+        OutputFormat::Html => {
+            buf.extend_from_slice(b"<tr>");
+            for cell in cells(headers, record) {
+                buf.extend_from_slice(format!("<td>{}</td>", escape_html(&cell)).as_bytes());
+            }
+            buf.extend_from_slice(b"</tr>\n");
+        }
+        OutputFormat::Csv => encode_csv_row(cells(headers, record), buf)?,
+        OutputFormat::Parquet | OutputFormat::Arrow => {
+            anyhow::bail!(
+                "{} output is columnar, only `rcli csv` conversion writes it",
+                format
+            )
+        }
+    }
+    Ok(())
+}
+
+pub fn encode_csv_row(
+    cells: impl Iterator<Item = String>,
+    buf: &mut Vec<u8>,
+) -> anyhow::Result<()> {
+    let mut writer = csv::WriterBuilder::new().from_writer(buf);
+    writer.write_record(cells.collect::<Vec<_>>())?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// CSV writer for an output path or stdout, in the dialect of the input
+pub fn csv_output(
+    output: Option<&str>,
+    reader: &CsvReaderOpts,
+) -> anyhow::Result<csv::Writer<Box<dyn Write>>> {
+    csv_writer(writer_for_output(output)?, reader)
+}
+
+/// CSV writer on top of any output, in the dialect of the input
+pub fn csv_writer(
+    output: Box<dyn Write>,
+    reader: &CsvReaderOpts,
+) -> anyhow::Result<csv::Writer<Box<dyn Write>>> {
+    Ok(csv::WriterBuilder::new()
+        .delimiter(ascii_byte(reader.delimiter)?)
+        .quote(ascii_byte(reader.quote)?)
+        .flexible(reader.flexible)
+        .from_writer(output))
+}
+
+/// pretty print one array element, indented as it would be inside the array
+fn encode_json(record: &Value, buf: &mut Vec<u8>) -> anyhow::Result<()> {
+    let mut element = Vec::with_capacity(256);
+    let mut ser = Serializer::with_formatter(&mut element, PrettyFormatter::with_indent(b"  "));
+    record.serialize(&mut ser)?;
+    buf.extend_from_slice(b"  ");
+    for &b in &element {
+        buf.push(b);
+        // json strings never contain a raw newline, so every newline is structural
+        if b == b'\n' {
+            buf.extend_from_slice(b"  ");
+        }
+    }
+    Ok(())
+}
+
+/// a single item sequence serializes to `- key: value` lines, which concatenate
+/// into the same sequence as the whole list
+fn encode_yaml(record: &Value, buf: &mut Vec<u8>) -> anyhow::Result<()> {
+    serde_yaml::to_writer(buf, &[record])?;
+    Ok(())
+}
+
+#[derive(Serialize)]
+struct TomlRecords<'a> {
+    records: [&'a Value; 1],
+}
+
+/// every record is one `[[records]]` table, toml has no null so empty cells are left out
+fn encode_toml(record: &Value, buf: &mut Vec<u8>) -> anyhow::Result<()> {
+    if !record.is_object() {
+        anyhow::bail!("TOML output requires records with named columns");
+    }
+    let record = strip_nulls(record, "")?;
+    let table = toml::to_string(&TomlRecords { records: [&record] })?;
+    buf.extend_from_slice(table.as_bytes());
+    Ok(())
+}
+
+/// Leave out null object entries and trailing null array elements. A null
+/// before other array elements cannot be left out without moving them to
+/// other indices, so it is an error.
+fn strip_nulls(value: &Value, path: &str) -> anyhow::Result<Value> {
+    match value {
+        Value::Object(map) => map
+            .iter()
+            .filter(|(_, v)| !v.is_null())
+            .map(|(k, v)| {
+                let path = match path {
+                    "" => k.clone(),
+                    _ => format!("{}.{}", path, k),
+                };
+                Ok((k.clone(), strip_nulls(v, &path)?))
+            })
+            .collect::<anyhow::Result<Map<_, _>>>()
+            .map(Value::from),
+        Value::Array(values) => {
+            let len = values
+                .iter()
+                .rposition(|v| !v.is_null())
+                .map_or(0, |i| i + 1);
+            values[..len]
+                .iter()
+                .enumerate()
+                .map(|(i, v)| {
+                    let path = format!("{}[{}]", path, i);
+                    if v.is_null() {
+                        anyhow::bail!("TOML cannot represent null at {}", path);
+                    }
+                    strip_nulls(v, &path)
+                })
+                .collect::<anyhow::Result<Vec<_>>>()
+                .map(Value::from)
+        }
+        _ => Ok(value.clone()),
+    }
+}
+
+/// cells of a record in header order, array records are positional
+fn cells<'a>(headers: &'a [String], record: &'a Value) -> impl Iterator<Item = String> + 'a {
+    headers.iter().enumerate().map(move |(i, h)| {
+        let cell = match record {
+            Value::Array(values) => values.get(i),
+            _ => record.get(h),
+        };
+        cell.map(value_to_cell).unwrap_or_default()
+    })
+}
+
+/// scalars are written as-is, nested values as their json text
+pub fn value_to_cell(value: &Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::String(s) => s.clone(),
+        _ => value.to_string(),
+    }
+}
+
+fn markdown_row(cells: impl Iterator<Item = String>) -> String {
+    let mut row = String::from("|");
+    for cell in cells {
+        row.push(' ');
+        row.push_str(&cell);
+        row.push_str(" |");
+    }
+    row.push('\n');
+    row
+}
+
+fn escape_markdown(s: &str) -> String {
+    s.replace('|', "\\|")
+        .replace("\r\n", "<br>")
+        .replace('\n', "<br>")
+}
+
+fn escape_html(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn stream(records: &[Value], format: OutputFormat) -> String {
+        let headers = vec!["Name".to_string(), "Kit Number".to_string()];
+        let mut writer = RecordWriter::new(Vec::new(), format, headers);
+        for record in records {
+            writer.write(record).unwrap();
+        }
+        String::from_utf8(writer.finish().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_stream_matches_whole_document() {
+        let records = vec![
+            json!({"Name": "Perin", "Kit Number": "37"}),
+            json!({"Name": "Buffon", "Kit Number": "77"}),
+        ];
+        assert_eq!(
+            stream(&records, OutputFormat::Json),
+            serde_json::to_string_pretty(&records).unwrap()
+        );
+        assert_eq!(
+            stream(&records, OutputFormat::Yaml),
+            serde_yaml::to_string(&records).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_stream_empty() {
+        let records: Vec<Value> = vec![];
+        assert_eq!(
+            stream(&records, OutputFormat::Json),
+            serde_json::to_string_pretty(&records).unwrap()
+        );
+        assert_eq!(
+            stream(&records, OutputFormat::Yaml),
+            serde_yaml::to_string(&records).unwrap()
+        );
+        assert_eq!(stream(&records, OutputFormat::Ndjson), "");
+        assert_eq!(
+            stream(&records, OutputFormat::Markdown),
+            "| Name | Kit Number |\n| --- | --- |\n"
+        );
+    }
+
+    #[test]
+    fn test_stream_tables() {
+        let records = vec![
+            json!({"Name": "A|B", "Kit Number": 1}),
+            json!({"Name": "<C>", "Kit Number": null}),
+        ];
+        assert_eq!(
+            stream(&records, OutputFormat::Markdown),
+            "| Name | Kit Number |\n| --- | --- |\n| A\\|B | 1 |\n| <C> |  |\n"
+        );
+        let html = stream(&records, OutputFormat::Html);
+        assert!(html.contains("<tr><th>Name</th><th>Kit Number</th></tr>"));
+        assert!(html.contains("<tr><td>&lt;C&gt;</td><td></td></tr>"));
+        assert!(html.ends_with("</table>\n</body>\n</html>\n"));
+
+        assert_eq!(
+            stream(&records, OutputFormat::Csv),
+            "Name,Kit Number\nA|B,1\n<C>,\n"
+        );
+
+        let toml = stream(&records, OutputFormat::Toml);
+        let parsed: toml::Value = toml::from_str(&toml).unwrap();
+        let rows = parsed["records"].as_array().unwrap();
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0]["Kit Number"].as_integer(), Some(1));
+        assert!(rows[1].get("Kit Number").is_none());
+    }
+
+    #[test]
+    fn test_toml_array_nulls() -> anyhow::Result<()> {
+        let mut buf = Vec::new();
+        encode_toml(&json!({"id": 1, "tags": ["a", null, null]}), &mut buf)?;
+        let parsed: toml::Value = toml::from_str(std::str::from_utf8(&buf)?)?;
+        assert_eq!(parsed["records"][0]["tags"], toml::Value::from(vec!["a"]));
+
+        let err = encode_toml(&json!({"id": 1, "x": {"tags": [null, "b"]}}), &mut buf);
+        assert_eq!(
+            err.unwrap_err().to_string(),
+            "TOML cannot represent null at x.tags[0]"
+        );
+        Ok(())
+    }
+}
@@ -1,45 +1,578 @@
-use crate::cli::GenPassOpts;
+use std::{
+    collections::HashSet,
+    fmt::{self, Display, Formatter},
+    fs, iter,
+    time::Duration,
+};
+
+use crate::cli::{GenPassOpts, PassOutput, WordCase};
 use rand::prelude::*;
+use serde::Serialize;
+use zxcvbn::zxcvbn;
+
+use super::gen_policy::PasswordPolicy;
 
 const UPPER: &str = "ABCDEFGHIJKLMNOPQRSTUVWXYZ";
 const LOWER: &str = "abcdefghijklmnopqrstuvwxyz";
-const NUMBER: &str = "123456789";
+const DIGITS: &str = "0123456789";
 const SYMBOL: &str = "!@#$%^&*_";
-pub fn process_genpass(opts: GenPassOpts) -> anyhow::Result<String> {
-    let mut password = String::new();
+/// characters easily mistaken for one another when read or typed
+const AMBIGUOUS: &str = "0OoQD1lIi|B8G6S5Z2`'\"";
+/// the BIP39 english list: 2048 common words, none sharing its first four letters
+const WORDLIST: &str = include_str!("wordlist.txt");
+const LENGTH: u8 = 16;
+const PIN_LENGTH: u8 = 6;
+/// arrangements tried before a policy counts as unsatisfiable
+const MAX_ATTEMPTS: usize = 100;
+
+/// A generated password with its strength estimate
+#[derive(Debug, Serialize)]
+pub struct PasswordReport {
+    pub password: String,
+    /// zxcvbn score, 0 (weakest) to 4
+    pub score: u8,
+    /// zxcvbn estimate against an offline attack on a slow hash (10k guesses/s)
+    pub crack_time: String,
+    pub crack_seconds: u64,
+    /// bits of entropy of the generator, see [`genpass_entropy`]
+    pub entropy: f64,
+}
+
+pub fn process_genpass(opts: &GenPassOpts) -> anyhow::Result<String> {
+    generate(opts, &load_policy(opts)?)
+}
+
+/// Generate `--count` passwords, each with its strength estimate
+pub fn process_genpass_batch(opts: &GenPassOpts) -> anyhow::Result<Vec<PasswordReport>> {
+    let policy = load_policy(opts)?;
+    let entropy = genpass_entropy(opts)?;
+    (0..opts.count)
+        .map(|_| {
+            let password = generate(opts, &policy)?;
+            let estimate = zxcvbn(&password, &[]);
+            let crack_time = estimate.crack_times().offline_slow_hashing_1e4_per_second();
+            Ok(PasswordReport {
+                score: estimate.score().into(),
+                crack_time: crack_time.to_string(),
+                crack_seconds: Duration::from(crack_time).as_secs(),
+                entropy,
+                password,
+            })
+        })
+        .collect()
+}
+
+pub fn format_reports(reports: &[PasswordReport], output: PassOutput) -> anyhow::Result<String> {
+    match output {
+        PassOutput::Plain => Ok(reports
+            .iter()
+            .map(|r| format!("{}\n", r.password))
+            .collect()),
+        PassOutput::Json => Ok(serde_json::to_string_pretty(reports)? + "\n"),
+        PassOutput::Csv => {
+            let mut writer = csv::Writer::from_writer(Vec::new());
+            for report in reports {
+                writer.serialize(report)?;
+            }
+            Ok(String::from_utf8(writer.into_inner()?)?)
+        }
+    }
+}
+
+/// Entropy of the generator in bits, what an attacker who knows the options
+/// has to search. Where digits land in a passphrase is not counted.
+pub fn genpass_entropy(opts: &GenPassOpts) -> anyhow::Result<f64> {
+    if let Some(words) = opts.words {
+        let list = load_words(opts.wordlist.as_deref())?;
+        let per_word = (list.len() as f64).log2()
+            + match opts.word_case {
+                WordCase::Random => 1.0,
+                _ => 0.0,
+            };
+        return Ok(words as f64 * per_word + opts.digits as f64 * 10f64.log2());
+    }
+    let classes = char_classes(opts, &load_policy(opts)?)?;
+    let charset = classes.iter().map(|class| class.chars.len()).sum::<usize>();
+    Ok(password_length(opts) as f64 * (charset as f64).log2())
+}
+
+fn password_length(opts: &GenPassOpts) -> usize {
+    let default = if opts.pin { PIN_LENGTH } else { LENGTH };
+    opts.length.unwrap_or(default) as usize
+}
+
+fn load_policy(opts: &GenPassOpts) -> anyhow::Result<PasswordPolicy> {
+    Ok(opts
+        .policy
+        .as_deref()
+        .map(PasswordPolicy::load)
+        .transpose()?
+        .unwrap_or_default())
+}
+
+fn generate(opts: &GenPassOpts, policy: &PasswordPolicy) -> anyhow::Result<String> {
+    match opts.words {
+        Some(words) => generate_passphrase(opts, words),
+        None => generate_password(opts, policy),
+    }
+}
+
+/// The characters of one enabled class and how many of them a password needs
+struct CharClass {
+    chars: Vec<char>,
+    min: usize,
+}
+
+/// Every enabled class of the alphabet, the built-in classes or `--charset`,
+/// without excluded and forbidden characters. Each one is used at least once,
+/// or as often as the policy asks.
+fn char_classes(opts: &GenPassOpts, policy: &PasswordPolicy) -> anyhow::Result<Vec<CharClass>> {
+    let alphabet = match &opts.charset {
+        Some(charset) => charset.clone(),
+        None => {
+            let symbols = opts.symbols.as_deref().or(policy.symbols.as_deref());
+            [UPPER, LOWER, DIGITS, symbols.unwrap_or(SYMBOL)].concat()
+        }
+    };
+    let mut seen = HashSet::new();
+    let alphabet = alphabet
+        .chars()
+        .filter(|c| seen.insert(*c))
+        .filter(|c| !policy.forbidden.contains(*c))
+        .filter(|c| !opts.exclude.as_deref().unwrap_or_default().contains(*c))
+        .filter(|c| !(opts.exclude_ambiguous && AMBIGUOUS.contains(*c)))
+        .filter(|c| match (CharKind::of(*c), &policy.symbols) {
+            (CharKind::Symbol, Some(symbols)) => symbols.contains(*c),
+            _ => true,
+        })
+        .collect::<Vec<_>>();
+    if let Some(c) = alphabet.iter().find(|c| c.is_control()) {
+        anyhow::bail!("The character set contains the control character {:?}", c);
+    }
+
+    let (uppercase, lowercase, number, symbol) = match opts.pin {
+        true => (false, false, true, false),
+        false => (opts.uppercase, opts.lowercase, opts.number, opts.symbol),
+    };
+    let mut classes = Vec::new();
+    for (kind, enabled, min) in [
+        (CharKind::Uppercase, uppercase, policy.min_uppercase),
+        (CharKind::Lowercase, lowercase, policy.min_lowercase),
+        (CharKind::Digit, number, policy.min_digits),
+        (CharKind::Symbol, symbol, policy.min_symbols),
+        (CharKind::Letter, uppercase || lowercase, 0),
+    ] {
+        let chars = alphabet
+            .iter()
+            .copied()
+            .filter(|c| CharKind::of(*c) == kind)
+            .collect::<Vec<_>>();
+        if min > 0 && (!enabled || chars.is_empty()) {
+            anyhow::bail!(
+                "The policy needs {} {} characters, but none can be used",
+                min,
+                kind
+            );
+        }
+        if enabled && !chars.is_empty() {
+            classes.push(CharClass {
+                chars,
+                min: min.max(1),
+            });
+        }
+    }
+    if classes.is_empty() {
+        anyhow::bail!("No characters left to generate a password from");
+    }
+    Ok(classes)
+}
+
+/// The class a character counts as, for the class options and policies
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum CharKind {
+    Uppercase,
+    Lowercase,
+    Digit,
+    Symbol,
+    /// letters without case, such as CJK characters
+    Letter,
+}
+
+impl CharKind {
+    fn of(c: char) -> Self {
+        if c.is_uppercase() {
+            CharKind::Uppercase
+        } else if c.is_lowercase() {
+            CharKind::Lowercase
+        } else if c.is_numeric() {
+            CharKind::Digit
+        } else if c.is_alphabetic() {
+            CharKind::Letter
+        } else {
+            CharKind::Symbol
+        }
+    }
+}
+
+impl Display for CharKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            CharKind::Uppercase => "uppercase",
+            CharKind::Lowercase => "lowercase",
+            CharKind::Digit => "digit",
+            CharKind::Symbol => "symbol",
+            CharKind::Letter => "letter",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// A password that follows `policy` by construction: the characters every
+/// class needs are placed first, then each position is drawn from what the
+/// repeat and sequence rules still allow. The result is checked once more.
+fn generate_password(opts: &GenPassOpts, policy: &PasswordPolicy) -> anyhow::Result<String> {
+    let length = password_length(opts);
+    let classes = char_classes(opts, policy)?;
+    if policy.min_length.is_some_and(|min| length < min)
+        || policy.max_length.is_some_and(|max| length > max)
+    {
+        anyhow::bail!(
+            "The policy allows lengths {} to {}, not {}",
+            policy.min_length.unwrap_or(1),
+            policy.max_length.unwrap_or(u8::MAX as usize),
+            length
+        );
+    }
+    let required = classes.iter().map(|class| class.min).sum::<usize>();
+    if required > length {
+        anyhow::bail!(
+            "Length {} is too short for the {} required characters, one of each class plus what the policy asks for",
+            length,
+            required
+        );
+    }
+    let is_letters = |class: &CharClass| class.chars.iter().all(|c| c.is_alphabetic());
+    if policy.start_with_letter && !classes.iter().any(is_letters) {
+        anyhow::bail!("The policy needs a leading letter, but no letters can be used");
+    }
+
     let mut rng = thread_rng();
-    let mut charset = String::new();
-    if opts.uppercase {
-        charset.push_str(UPPER);
-        let x = rng.gen_range(0..UPPER.len());
-        password.push(UPPER.as_bytes()[x] as char);
+    for _ in 0..MAX_ATTEMPTS {
+        // the class each position is drawn from, None for any class
+        let mut slots = classes
+            .iter()
+            .enumerate()
+            .flat_map(|(i, class)| iter::repeat_n(Some(i), class.min))
+            .chain(iter::repeat(None))
+            .take(length)
+            .collect::<Vec<_>>();
+        slots.shuffle(&mut rng);
+        if policy.start_with_letter {
+            let first = slots
+                .iter()
+                .position(|slot| slot.is_none_or(|i| is_letters(&classes[i])));
+            slots.swap(0, first.unwrap_or(0));
+        }
+
+        let mut password = Vec::with_capacity(length);
+        for (pos, slot) in slots.iter().enumerate() {
+            let candidates = match slot {
+                Some(i) => classes[*i].chars.iter().collect::<Vec<_>>(),
+                None => classes.iter().flat_map(|class| &class.chars).collect(),
+            };
+            let allowed = candidates
+                .into_iter()
+                .filter(|c| pos > 0 || !policy.start_with_letter || c.is_alphabetic())
+                .filter(|c| policy.allows_next(&password, **c))
+                .collect::<Vec<_>>();
+            match allowed.choose(&mut rng) {
+                Some(c) => password.push(**c),
+                None => break,
+            }
+        }
+        if password.len() < length {
+            continue;
+        }
+
+        let password = password.into_iter().collect::<String>();
+        let violations = policy.check(&password);
+        if !violations.is_empty() {
+            anyhow::bail!(
+                "Generated password breaks the policy: {}",
+                violations.join(", ")
+            );
+        }
+        return Ok(password);
     }
-    if opts.lowercase {
-        charset.push_str(LOWER);
-        let x = rng.gen_range(0..LOWER.len());
-        password.push(LOWER.as_bytes()[x] as char);
+    anyhow::bail!(
+        "No password of length {} follows the policy after {} attempts",
+        length,
+        MAX_ATTEMPTS
+    )
+}
+
+fn generate_passphrase(opts: &GenPassOpts, count: u8) -> anyhow::Result<String> {
+    let list = load_words(opts.wordlist.as_deref())?;
+    let mut rng = thread_rng();
+    let mut words = (0..count)
+        .map(|_| {
+            let word = &list[rng.gen_range(0..list.len())];
+            match opts.word_case {
+                WordCase::Lower => word.to_lowercase(),
+                WordCase::Upper => word.to_uppercase(),
+                WordCase::Title => title_case(word),
+                WordCase::Random if rng.gen() => title_case(word),
+                WordCase::Random => word.to_lowercase(),
+            }
+        })
+        .collect::<Vec<_>>();
+    // every digit goes at the end of a random word, which keeps the words readable
+    for _ in 0..opts.digits {
+        let digit = DIGITS.as_bytes()[rng.gen_range(0..DIGITS.len())] as char;
+        let i = rng.gen_range(0..words.len());
+        words[i].push(digit);
     }
-    if opts.number {
-        charset.push_str(NUMBER);
-        let x = rng.gen_range(0..NUMBER.len());
-        password.push(NUMBER.as_bytes()[x] as char);
+    Ok(words.join(&opts.separator))
+}
+
+/// The embedded list or the words of a list file. A diceware line holds the
+/// dice roll and then the word, repeated words are only counted once.
+fn load_words(path: Option<&str>) -> anyhow::Result<Vec<String>> {
+    let content = match path {
+        Some(path) => fs::read_to_string(path)?,
+        None => WORDLIST.to_string(),
+    };
+    let mut seen = HashSet::new();
+    let words = content
+        .lines()
+        .filter_map(|line| line.split_whitespace().last())
+        .filter(|word| seen.insert(*word))
+        .map(String::from)
+        .collect::<Vec<_>>();
+    if words.len() < 2 {
+        anyhow::bail!("A word list needs at least two different words");
     }
-    if opts.symbol {
-        charset.push_str(SYMBOL);
-        let x = rng.gen_range(0..SYMBOL.len());
-        password.push(SYMBOL.as_bytes()[x] as char);
+    Ok(words)
+}
+
+fn title_case(word: &str) -> String {
+    let mut chars = word.chars();
+    match chars.next() {
+        Some(first) => first
+            .to_uppercase()
+            .chain(chars.flat_map(char::to_lowercase))
+            .collect(),
+        None => String::new(),
     }
-    let charset = charset.as_bytes();
-    for _ in 0..(opts.length - password.len() as u8) {
-        let idx = rng.gen_range(0..charset.len());
-        password.push(charset[idx] as char);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    #[test]
+    fn test_passphrase() -> anyhow::Result<()> {
+        let args = [
+            "genpass",
+            "--words",
+            "6",
+            "--separator",
+            " ",
+            "--case",
+            "title",
+            "--digits",
+            "2",
+        ];
+        let phrase = process_genpass(&GenPassOpts::parse_from(args))?;
+        let words = phrase.split(' ').collect::<Vec<_>>();
+        assert_eq!(words.len(), 6);
+        assert!(words
+            .iter()
+            .all(|w| w.starts_with(|c: char| c.is_ascii_uppercase())));
+        assert_eq!(phrase.chars().filter(char::is_ascii_digit).count(), 2);
+
+        let entropy = genpass_entropy(&GenPassOpts::parse_from(args))?;
+        assert!((entropy - (66.0 + 2.0 * 10f64.log2())).abs() < 1e-9);
+        Ok(())
+    }
+
+    #[test]
+    fn test_default_opts() {
+        let parsed = GenPassOpts::parse_from(["genpass"]);
+        assert_eq!(
+            format!("{:?}", GenPassOpts::default()),
+            format!("{:?}", parsed)
+        );
     }
-    let mut password_chars = password.chars().collect::<Vec<char>>();
-    password_chars.shuffle(&mut rng);
 
-    password = password_chars.into_iter().collect::<String>();
+    #[test]
+    fn test_wordlist_file() -> anyhow::Result<()> {
+        let file = tempfile::NamedTempFile::new()?;
+        fs::write(
+            file.path(),
+            "11111\tabacus\n11112\tabdomen\n\n11113 abacus\n",
+        )?;
+        let path = file.path().to_str().unwrap();
+        let opts = GenPassOpts::parse_from(["genpass", "--words", "4", "--wordlist", path]);
+        assert_eq!(genpass_entropy(&opts)?, 4.0);
+        let phrase = process_genpass(&opts)?;
+        assert!(phrase.split('-').all(|w| w == "abacus" || w == "abdomen"));
 
-    // print!("{}", password);
+        // a full diceware list such as the EFF large list: 6^5 rolls, one word each
+        let mut rolls = vec![String::new()];
+        for _ in 0..5 {
+            rolls = rolls
+                .iter()
+                .flat_map(|roll| (1..=6).map(move |die| format!("{}{}", roll, die)))
+                .collect();
+        }
+        let list = rolls
+            .iter()
+            .map(|roll| format!("{}\tword{}\n", roll, roll))
+            .collect::<String>();
+        fs::write(file.path(), list)?;
+        let opts = GenPassOpts::parse_from(["genpass", "--words", "6", "--wordlist", path]);
+        let entropy = genpass_entropy(&opts)?;
+        assert!((entropy - 6.0 * 7776f64.log2()).abs() < 1e-9);
+        assert!(entropy > 77.5);
 
-    Ok(password)
+        fs::write(file.path(), "only\nonly\n")?;
+        let opts = GenPassOpts::parse_from(["genpass", "--words", "4", "--wordlist", path]);
+        assert!(process_genpass(&opts).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_batch_reports() -> anyhow::Result<()> {
+        let opts = GenPassOpts::parse_from(["genpass", "--count", "5", "--output", "json"]);
+        let reports = process_genpass_batch(&opts)?;
+        assert_eq!(reports.len(), 5);
+        assert!(reports
+            .iter()
+            .all(|r| r.password.len() == 16 && r.score <= 4));
+
+        let json: serde_json::Value =
+            serde_json::from_str(&format_reports(&reports, opts.output)?)?;
+        assert_eq!(json[4]["password"], reports[4].password.as_str());
+        assert!(json[0]["entropy"].as_f64().unwrap() > 90.0);
+
+        let csv = format_reports(&reports, PassOutput::Csv)?;
+        let mut lines = csv.lines();
+        assert_eq!(
+            lines.next(),
+            Some("password,score,crack_time,crack_seconds,entropy")
+        );
+        assert_eq!(lines.count(), 5);
+        assert_eq!(
+            format_reports(&reports, PassOutput::Plain)?.lines().count(),
+            5
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_policy_presets() -> anyhow::Result<()> {
+        for preset in ["aws-iam", "active-directory", "mysql"] {
+            let opts = GenPassOpts::parse_from(["genpass", "--policy", preset, "--count", "20"]);
+            let policy = load_policy(&opts)?;
+            for report in process_genpass_batch(&opts)? {
+                assert_eq!(policy.check(&report.password), Vec::<String>::new());
+            }
+        }
+        let opts = GenPassOpts::parse_from(["genpass", "--policy", "mysql", "--length", "40"]);
+        assert!(process_genpass(&opts).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_policy_file() -> anyhow::Result<()> {
+        let file = tempfile::NamedTempFile::new()?;
+        fs::write(
+            file.path(),
+            "min_digits = 4\nforbidden = \"aeiouAEIOU\"\nmax_repeats = 1\n\
+             no_sequences = true\nstart_with_letter = true\nsymbols = \"-\"\n",
+        )?;
+        let path = file.path().to_str().unwrap();
+        let opts = GenPassOpts::parse_from(["genpass", "--policy", path, "--count", "50"]);
+        for report in process_genpass_batch(&opts)? {
+            let password = report.password;
+            assert!(password.starts_with(|c: char| c.is_ascii_alphabetic()));
+            assert!(password.chars().filter(char::is_ascii_digit).count() >= 4);
+            assert!(!password.contains(|c: char| "aeiouAEIOU".contains(c)));
+            assert!(password
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-'));
+            let chars = password.chars().collect::<Vec<_>>();
+            assert!(chars.windows(2).all(|w| w[0] != w[1]));
+        }
+
+        // four digits and one of each other class do not fit in six characters
+        let opts = GenPassOpts::parse_from(["genpass", "--policy", path, "--length", "6"]);
+        assert!(process_genpass(&opts).is_err());
+        // a single allowed character cannot avoid repeating itself
+        fs::write(file.path(), "max_repeats = 1\nsymbols = \"#\"\n")?;
+        let mut opts = GenPassOpts::parse_from(["genpass", "--policy", path]);
+        (opts.uppercase, opts.lowercase, opts.number) = (false, false, false);
+        assert!(process_genpass(&opts).is_err());
+        fs::write(file.path(), "min_symbols = 2\n")?;
+        opts.symbol = false;
+        assert!(process_genpass(&opts).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_charset() -> anyhow::Result<()> {
+        let opts = GenPassOpts::parse_from(["genpass", "--charset", "αβγδΔ012", "-c", "20"]);
+        for report in process_genpass_batch(&opts)? {
+            let password = report.password;
+            assert_eq!(password.chars().count(), 16);
+            assert!(password.chars().all(|c| "αβγδΔ012".contains(c)));
+            assert!(password.contains('Δ'));
+        }
+        let opts = GenPassOpts::parse_from(["genpass", "--charset", "abcabc", "-l", "10"]);
+        assert!((genpass_entropy(&opts)? - 10.0 * 3f64.log2()).abs() < 1e-9);
+
+        // kanji have no case, they form a class of their own
+        let opts = GenPassOpts::parse_from(["genpass", "--charset", "東京1", "-l", "4"]);
+        let password = process_genpass(&opts)?;
+        assert!(password.contains('1') && password.contains(['東', '京']));
+
+        let opts = GenPassOpts::parse_from(["genpass", "--charset", "ab\tc"]);
+        assert!(process_genpass(&opts).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_exclude() -> anyhow::Result<()> {
+        let args = [
+            "genpass",
+            "--exclude-ambiguous",
+            "--exclude",
+            "xyz",
+            "--symbols",
+            "-+",
+            "-c",
+            "50",
+        ];
+        let passwords = process_genpass_batch(&GenPassOpts::parse_from(args))?
+            .into_iter()
+            .map(|r| r.password)
+            .collect::<String>();
+        assert!(!passwords.contains(|c: char| AMBIGUOUS.contains(c) || "xyz".contains(c)));
+        assert!(passwords
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '+'));
+        assert!(passwords.contains('9') && passwords.contains('-'));
+
+        let digits = process_genpass_batch(&GenPassOpts::parse_from(["genpass", "-c", "50"]))?
+            .into_iter()
+            .map(|r| r.password)
+            .collect::<String>();
+        assert!(digits.contains('0'));
+
+        let opts = GenPassOpts::parse_from(["genpass", "--exclude", "!@#$%^&*_"]);
+        assert!(process_genpass(&opts)?.chars().all(char::is_alphanumeric));
+        Ok(())
+    }
 }
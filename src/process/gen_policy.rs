@@ -0,0 +1,202 @@
+use std::{fs, path::Path};
+
+use serde::Deserialize;
+
+/// Rules a generated password has to follow, read from a TOML file or a preset.
+/// Every field is optional.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PasswordPolicy {
+    pub min_length: Option<usize>,
+    pub max_length: Option<usize>,
+    pub min_uppercase: usize,
+    pub min_lowercase: usize,
+    pub min_digits: usize,
+    pub min_symbols: usize,
+    /// the symbols the target accepts, instead of the default ones
+    pub symbols: Option<String>,
+    /// characters never used
+    pub forbidden: String,
+    /// longest run of one character, `aa` is a run of 2
+    pub max_repeats: Option<usize>,
+    /// no three characters counting up or down, such as `abc` or `321`
+    pub no_sequences: bool,
+    pub start_with_letter: bool,
+}
+
+const PRESETS: [(&str, &str); 3] = [
+    (
+        "aws-iam",
+        r#"
+        # AWS IAM account password policy requiring every character type
+        min_length = 8
+        max_length = 128
+        min_uppercase = 1
+        min_lowercase = 1
+        min_digits = 1
+        min_symbols = 1
+        symbols = "!@#$%^&*()_+-=[]{}|'"
+        "#,
+    ),
+    (
+        "active-directory",
+        r#"
+        # Active Directory "password must meet complexity requirements"
+        min_length = 7
+        max_length = 256
+        min_uppercase = 1
+        min_lowercase = 1
+        min_digits = 1
+        "#,
+    ),
+    (
+        "mysql",
+        r#"
+        # MySQL validate_password MEDIUM policy
+        min_length = 8
+        max_length = 32
+        min_uppercase = 1
+        min_lowercase = 1
+        min_digits = 1
+        min_symbols = 1
+        "#,
+    ),
+];
+
+impl PasswordPolicy {
+    /// `name` is a policy file or one of the presets
+    pub fn load(name: &str) -> anyhow::Result<Self> {
+        let content = if Path::new(name).is_file() {
+            fs::read_to_string(name)?
+        } else {
+            match PRESETS.iter().find(|(preset, _)| *preset == name) {
+                Some((_, content)) => content.to_string(),
+                None => anyhow::bail!(
+                    "No policy file or preset {:?}, the presets are {}",
+                    name,
+                    PRESETS.map(|(preset, _)| preset).join(", ")
+                ),
+            }
+        };
+        let policy: Self = toml::from_str(&content)?;
+        if policy.max_repeats == Some(0) {
+            anyhow::bail!("max_repeats must be at least 1");
+        }
+        Ok(policy)
+    }
+
+    /// whether `c` can follow `prefix` without breaking the repeat or sequence rules
+    pub fn allows_next(&self, prefix: &[char], c: char) -> bool {
+        if let Some(max) = self.max_repeats {
+            let run = prefix.iter().rev().take_while(|&&p| p == c).count();
+            if run + 1 > max {
+                return false;
+            }
+        }
+        if self.no_sequences {
+            if let [.., a, b] = prefix {
+                if is_sequence(*a, *b, c) {
+                    return false;
+                }
+            }
+        }
+        true
+    }
+
+    /// Every rule `password` breaks, empty when it follows the policy
+    pub fn check(&self, password: &str) -> Vec<String> {
+        let chars = password.chars().collect::<Vec<_>>();
+        let mut violations = Vec::new();
+        if let Some(min) = self.min_length.filter(|&min| chars.len() < min) {
+            violations.push(format!("shorter than {}", min));
+        }
+        if let Some(max) = self.max_length.filter(|&max| chars.len() > max) {
+            violations.push(format!("longer than {}", max));
+        }
+        let counts = [
+            (
+                "uppercase",
+                self.min_uppercase,
+                count(&chars, char::is_uppercase),
+            ),
+            (
+                "lowercase",
+                self.min_lowercase,
+                count(&chars, char::is_lowercase),
+            ),
+            ("digit", self.min_digits, count(&chars, char::is_numeric)),
+            (
+                "symbol",
+                self.min_symbols,
+                count(&chars, |c| !c.is_alphanumeric()),
+            ),
+        ];
+        for (class, min, found) in counts {
+            if found < min {
+                violations.push(format!("{} {} characters, {} needed", found, class, min));
+            }
+        }
+        for &c in &chars {
+            if self.forbidden.contains(c) {
+                violations.push(format!("forbidden character {:?}", c));
+            }
+            if let Some(symbols) = &self.symbols {
+                if !c.is_alphanumeric() && !symbols.contains(c) {
+                    violations.push(format!("symbol {:?} is not allowed", c));
+                }
+            }
+        }
+        for i in 0..chars.len() {
+            if !self.allows_next(&chars[..i], chars[i]) {
+                violations.push(format!("repeat or sequence ending at position {}", i + 1));
+            }
+        }
+        if self.start_with_letter && !chars.first().is_some_and(|c| c.is_alphabetic()) {
+            violations.push("does not start with a letter".to_string());
+        }
+        violations
+    }
+}
+
+fn count(chars: &[char], class: impl Fn(char) -> bool) -> usize {
+    chars.iter().filter(|&&c| class(c)).count()
+}
+
+/// three letters or digits counting up or down by one
+fn is_sequence(a: char, b: char, c: char) -> bool {
+    let step = b as i64 - a as i64;
+    [a, b, c].iter().all(|c| c.is_alphanumeric()) && step.abs() == 1 && c as i64 - b as i64 == step
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_presets_load() -> anyhow::Result<()> {
+        for (name, _) in PRESETS {
+            PasswordPolicy::load(name)?;
+        }
+        assert_eq!(PasswordPolicy::load("aws-iam")?.min_symbols, 1);
+        assert!(PasswordPolicy::load("windows-xp").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_check() {
+        let policy = PasswordPolicy {
+            min_digits: 2,
+            forbidden: "l".into(),
+            max_repeats: Some(2),
+            no_sequences: true,
+            start_with_letter: true,
+            ..Default::default()
+        };
+        assert!(policy.check("a9x8Q!").is_empty());
+        assert_eq!(policy.check("9abcl").len(), 4);
+        assert_eq!(policy.check("bb9b9"), Vec::<String>::new());
+        assert_eq!(policy.check("bbb99").len(), 1);
+        assert!(!policy.allows_next(&['3', '2'], '1'));
+        assert!(policy.allows_next(&['!', '"'], '#'));
+    }
+}
@@ -1,13 +1,41 @@
 mod b64;
+mod csv_columnar;
 mod csv_convert;
+mod csv_diff;
+mod csv_encoding;
+mod csv_excel;
+mod csv_filter;
+mod csv_infer;
+mod csv_join;
+mod csv_nested;
+mod csv_ops;
+mod csv_query;
+mod csv_reader;
+mod csv_sort;
+mod csv_stats;
+mod csv_validate;
+mod csv_view;
+mod csv_writer;
 mod gen_pass;
+mod gen_policy;
 mod http_serve;
 mod jwt;
+mod table;
 mod text;
 
 pub use b64::{process_decode, process_encode};
-pub use csv_convert::process_csv;
-pub use gen_pass::process_genpass;
+pub use csv_convert::{process_csv, process_to_csv};
+pub use csv_diff::process_csv_diff;
+pub use csv_infer::ColumnType;
+pub use csv_join::process_csv_join;
+pub use csv_ops::{process_csv_cat, process_csv_dedup, process_csv_sort, process_csv_split};
+pub use csv_query::process_csv_query;
+pub use csv_stats::process_csv_stats;
+pub use csv_validate::process_csv_validate;
+pub use csv_view::process_csv_view;
+pub use gen_pass::{
+    format_reports, genpass_entropy, process_genpass, process_genpass_batch, PasswordReport,
+};
 pub use http_serve::process_http_serve;
 pub use jwt::{process_jwt_sign, process_jwt_verify};
 pub use text::{
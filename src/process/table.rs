@@ -0,0 +1,142 @@
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
+
+/// Layout of one table column, in terminal cells
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ColumnLayout {
+    pub width: usize,
+    /// numbers read better lined up on the right
+    pub right: bool,
+}
+
+/// Render rows as a plain text table with space padded, left aligned columns
+pub fn render_table(headers: &[String], rows: &[Vec<String>]) -> String {
+    let layout = column_widths(headers, rows, usize::MAX)
+        .into_iter()
+        .map(|width| ColumnLayout {
+            width,
+            right: false,
+        })
+        .collect::<Vec<_>>();
+    render_rows(headers, rows, &layout)
+}
+
+/// Display width of every column over the header and `rows`, capped at
+/// `max_width`. East Asian wide characters count as two cells.
+pub fn column_widths(headers: &[String], rows: &[Vec<String>], max_width: usize) -> Vec<usize> {
+    let mut widths = headers.iter().map(|h| display_width(h)).collect::<Vec<_>>();
+    for row in rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(display_width(cell));
+        }
+    }
+    widths.into_iter().map(|w| w.min(max_width)).collect()
+}
+
+/// Render rows in fixed width columns, cells wider than their column are cut
+/// with `…`
+pub fn render_rows(headers: &[String], rows: &[Vec<String>], layout: &[ColumnLayout]) -> String {
+    let mut table = String::new();
+    let header_layout = layout
+        .iter()
+        .map(|c| ColumnLayout { right: false, ..*c })
+        .collect::<Vec<_>>();
+    push_row(&mut table, headers, &header_layout);
+    let rule = layout
+        .iter()
+        .map(|c| "-".repeat(c.width))
+        .collect::<Vec<_>>();
+    push_row(&mut table, &rule, layout);
+    for row in rows {
+        push_row(&mut table, row, layout);
+    }
+    table
+}
+
+fn push_row(table: &mut String, cells: &[String], layout: &[ColumnLayout]) {
+    let line = layout
+        .iter()
+        .enumerate()
+        .map(|(i, column)| {
+            let cell = fit(
+                cells.get(i).map(String::as_str).unwrap_or_default(),
+                column.width,
+            );
+            let pad = " ".repeat(column.width.saturating_sub(cell.width()));
+            if column.right {
+                format!("{}{}", pad, cell)
+            } else {
+                format!("{}{}", cell, pad)
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("  ");
+    table.push_str(line.trim_end());
+    table.push('\n');
+}
+
+/// control characters count as the one space they are shown as
+fn display_width(s: &str) -> usize {
+    s.chars().map(|c| c.width().unwrap_or(1)).sum()
+}
+
+/// `s` on one line and at most `width` cells wide, line breaks and tabs would
+/// break the row apart so they show as spaces
+fn fit(s: &str, width: usize) -> String {
+    let line = s
+        .chars()
+        .map(|c| if c.is_control() { ' ' } else { c })
+        .collect::<String>();
+    if line.width() <= width {
+        return line;
+    }
+    let mut cut = String::new();
+    let mut used = 0;
+    for c in line.chars() {
+        let w = c.width().unwrap_or(0);
+        // keep one cell for the ellipsis
+        if used + w + 1 > width {
+            break;
+        }
+        cut.push(c);
+        used += w;
+    }
+    if width > 0 {
+        cut.push('…');
+    }
+    cut
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_rows() {
+        let headers = ["Name", "Kit"].map(String::from);
+        let rows = vec![
+            vec!["Buffon".to_string(), "77".to_string()],
+            vec!["本田圭佑です".to_string(), "4".to_string()],
+        ];
+        let widths = column_widths(&headers, &rows, 8);
+        assert_eq!(widths, [8, 3]);
+        let layout = [
+            ColumnLayout {
+                width: widths[0],
+                right: false,
+            },
+            ColumnLayout {
+                width: widths[1],
+                right: true,
+            },
+        ];
+        assert_eq!(
+            render_rows(&headers, &rows, &layout),
+            "Name      Kit\n--------  ---\nBuffon     77\n本田圭…     4\n"
+        );
+        assert_eq!(fit("a\nb", 5), "a b");
+        assert_eq!(
+            render_table(&headers, &rows[..1]),
+            "Name    Kit\n------  ---\nBuffon  77\n"
+        );
+    }
+}
@@ -276,13 +276,10 @@ impl ED25519Verifier {
 impl KeyGenerator for Blake3 {
     fn generate() -> anyhow::Result<Vec<Vec<u8>>> {
         let opts = GenPassOpts {
-            length: 32,
-            uppercase: true,
-            lowercase: true,
-            number: true,
-            symbol: true,
+            length: Some(32),
+            ..Default::default()
         };
-        let key = process_genpass(opts)?;
+        let key = process_genpass(&opts)?;
         Ok(vec![key.as_bytes().to_vec()])
     }
 }
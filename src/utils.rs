@@ -1,4 +1,4 @@
-use std::io::Read;
+use std::io::{self, BufWriter, ErrorKind, Read, Write};
 
 pub fn reader_from_input(input: &str) -> anyhow::Result<String> {
     let mut reader: Box<dyn Read> = if input == "-" {
@@ -25,3 +25,39 @@ pub fn reader_from_input_u8(input: &str) -> anyhow::Result<Vec<u8>> {
     // buffer = buffer.trim().to_string();
     Ok(buffer)
 }
+
+/// Buffered writer for an output path, stdout when the path is omitted or `-`
+pub fn writer_for_output(output: Option<&str>) -> anyhow::Result<Box<dyn Write + Send>> {
+    let writer: Box<dyn Write + Send> = match output {
+        Some(path) if path != "-" => Box::new(BufWriter::new(std::fs::File::create(path)?)),
+        _ => Box::new(BufWriter::new(std::io::stdout())),
+    };
+    Ok(writer)
+}
+
+/// A reader closing the output pipe early, such as `head`, ends the command
+/// cleanly instead of with an error
+pub fn ignore_broken_pipe(result: anyhow::Result<()>) -> anyhow::Result<()> {
+    match result {
+        Err(e) if is_broken_pipe(&e) => Ok(()),
+        result => result,
+    }
+}
+
+fn is_broken_pipe(e: &anyhow::Error) -> bool {
+    e.chain().any(|cause| {
+        let kind = if let Some(e) = cause.downcast_ref::<io::Error>() {
+            Some(e.kind())
+        } else if let Some(e) = cause.downcast_ref::<csv::Error>() {
+            match e.kind() {
+                csv::ErrorKind::Io(e) => Some(e.kind()),
+                _ => None,
+            }
+        } else if let Some(e) = cause.downcast_ref::<serde_json::Error>() {
+            e.io_error_kind()
+        } else {
+            None
+        };
+        kind == Some(ErrorKind::BrokenPipe)
+    })
+}
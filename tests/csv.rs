@@ -0,0 +1,76 @@
+use std::{
+    io::{BufRead, BufReader, Write},
+    process::{Command, Output, Stdio},
+    thread,
+};
+
+const SQUAD: &str = "Name,Kit Number\nBuffon,77\nChiellini,3\n";
+
+fn rcli(args: &[&str], stdin: &[u8]) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_rcli"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(stdin).unwrap();
+    child.wait_with_output().unwrap()
+}
+
+#[test]
+fn test_stdin_to_stdout() {
+    let expected = "{\"Name\":\"Buffon\",\"Kit Number\":77}\n\
+                    {\"Name\":\"Chiellini\",\"Kit Number\":3}\n";
+    for args in [
+        &["csv", "--format", "ndjson"][..],
+        &["csv", "-i", "-", "--format", "ndjson", "-o", "-"][..],
+    ] {
+        let output = rcli(args, SQUAD.as_bytes());
+        assert!(output.status.success(), "{:?}", output);
+        assert_eq!(String::from_utf8_lossy(&output.stdout), expected);
+    }
+
+    let output = rcli(&["csv", "sort", "--key", "Kit Number:n"], SQUAD.as_bytes());
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "Name,Kit Number\nChiellini,3\nBuffon,77\n"
+    );
+}
+
+#[test]
+fn test_closed_pipe_exits_cleanly() {
+    // far more output than a pipe buffers, so rcli is still writing when the
+    // reader goes away
+    let mut input = String::from("id,name\n");
+    for i in 0..200_000 {
+        input.push_str(&format!("{},player {}\n", i, i));
+    }
+    let mut child = Command::new(env!("CARGO_BIN_EXE_rcli"))
+        .args(["csv", "--format", "ndjson", "--threads", "1"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut stdin = child.stdin.take().unwrap();
+    let feeder = thread::spawn(move || {
+        // rcli may stop reading once its output is closed
+        let _ = stdin.write_all(input.as_bytes());
+    });
+
+    let mut stdout = BufReader::new(child.stdout.take().unwrap());
+    let mut line = String::new();
+    stdout.read_line(&mut line).unwrap();
+    assert_eq!(line, "{\"id\":0,\"name\":\"player 0\"}\n");
+    drop(stdout);
+
+    let output = child.wait_with_output().unwrap();
+    feeder.join().unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+}
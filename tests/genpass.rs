@@ -0,0 +1,123 @@
+use std::process::Command;
+
+use clap::Parser;
+use rcli::{process_genpass, GenPassOpts};
+
+/// a class option and how to recognize its characters
+type Class = (&'static str, fn(char) -> bool);
+
+const CLASSES: [Class; 4] = [
+    ("--uppercase", |c| c.is_ascii_uppercase()),
+    ("--lowercase", |c| c.is_ascii_lowercase()),
+    ("--number", |c| c.is_ascii_digit()),
+    ("--symbol", |c| !c.is_ascii_alphanumeric()),
+];
+
+fn genpass(args: &[&str]) -> anyhow::Result<String> {
+    let argv = ["genpass"].iter().chain(args).copied();
+    process_genpass(&GenPassOpts::try_parse_from(argv)?)
+}
+
+#[test]
+fn test_every_class_combination() -> anyhow::Result<()> {
+    for mask in 0..16 {
+        let enabled = (0..4).map(|i| mask & (1 << i) != 0).collect::<Vec<_>>();
+        let mut args = Vec::new();
+        for (i, (flag, _)) in CLASSES.iter().enumerate() {
+            args.extend([*flag, if enabled[i] { "true" } else { "false" }]);
+        }
+        let classes = enabled.iter().filter(|e| **e).count();
+        if classes == 0 {
+            assert!(genpass(&args).is_err(), "no classes enabled");
+            continue;
+        }
+
+        for length in [classes, 16, 64] {
+            let length_arg = length.to_string();
+            let mut args = args.clone();
+            args.extend(["--length", &length_arg]);
+            let password = genpass(&args)?;
+            assert_eq!(password.len(), length, "{:?}", args);
+            for (i, (flag, is_class)) in CLASSES.iter().enumerate() {
+                let found = password.chars().any(is_class);
+                assert_eq!(
+                    found, enabled[i],
+                    "{} in {:?} from {:?}",
+                    flag, password, args
+                );
+            }
+        }
+
+        // shorter than one character of each enabled class
+        let length_arg = (classes - 1).to_string();
+        let mut args = args.clone();
+        args.extend(["--length", &length_arg]);
+        assert!(genpass(&args).is_err(), "{:?}", args);
+    }
+    Ok(())
+}
+
+#[test]
+fn test_flags_without_value() -> anyhow::Result<()> {
+    let password = genpass(&["--symbol", "--number", "false", "--uppercase", "false"])?;
+    assert!(password
+        .chars()
+        .all(|c| c.is_ascii_lowercase() || !c.is_alphanumeric()));
+    assert!(password.chars().any(|c| !c.is_alphanumeric()));
+    Ok(())
+}
+
+#[test]
+fn test_pin() -> anyhow::Result<()> {
+    let pin = genpass(&["--pin"])?;
+    assert_eq!(pin.len(), 6);
+    assert!(pin.chars().all(|c| c.is_ascii_digit()));
+
+    let pin = genpass(&["--pin", "--length", "4", "--symbol", "true"])?;
+    assert_eq!(pin.len(), 4);
+    assert!(pin.chars().all(|c| c.is_ascii_digit()));
+
+    assert!(genpass(&["--pin", "--words", "4"]).is_err());
+    assert!(genpass(&["--pin", "--charset", "abc"]).is_err());
+    Ok(())
+}
+
+#[test]
+fn test_short_length_fails_without_panic() {
+    for length in ["0", "1", "3"] {
+        let output = Command::new(env!("CARGO_BIN_EXE_rcli"))
+            .args(["genpass", "--length", length])
+            .output()
+            .unwrap();
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(!output.status.success());
+        assert!(stderr.contains("too short"), "{}", stderr);
+        assert!(!stderr.contains("panicked"), "{}", stderr);
+    }
+
+    let output = Command::new(env!("CARGO_BIN_EXE_rcli"))
+        .args(["genpass", "--length", "4"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout).trim_end().len(), 4);
+}
+
+#[test]
+fn test_passphrase_options_need_words() -> anyhow::Result<()> {
+    for args in [
+        &["--separator", "_"][..],
+        &["--case", "title"][..],
+        &["--digits", "2"][..],
+    ] {
+        assert!(genpass(args).is_err(), "{:?}", args);
+        let mut with_words = vec!["--words", "3"];
+        with_words.extend(args);
+        genpass(&with_words)?;
+    }
+
+    let err = GenPassOpts::try_parse_from(["genpass", "--words", "0"]).unwrap_err();
+    assert!(err.to_string().contains("1..=255"), "{}", err);
+    assert!(GenPassOpts::try_parse_from(["genpass", "--words", "256"]).is_err());
+    Ok(())
+}